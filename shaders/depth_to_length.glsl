@@ -9,31 +9,33 @@ layout(binding = 1, r32f) uniform writeonly image2D outputDepth;
 
 
 layout(push_constant) uniform PushConsts {
-    vec3 pos;
-    // ivec3 gridPos;
-    mat4 projInv;
-    mat4 viewInv;
+    // the same matrix the rays are generated with, see ray_generation.glsl
+    mat4 pixelToCamera;
 } pushConsts;
 
+// Pixels without rasterized geometry, the same as NO_MAX_DISTANCE in tree_traverse.glsl
+const float NO_RASTER_LENGTH = 1e30;
+
+
+vec3 unproject(vec2 pixel, float depth)
+{
+    vec4 position = pushConsts.pixelToCamera * vec4(pixel, depth, 1.0);
+    return position.xyz / position.w;
+}
 
 void main()
 {
     ivec2 pixelPos = ivec2(gl_GlobalInvocationID.xy);
     float depth = texelFetch(rasterDepth, pixelPos, 0).r;
 
-    vec2 dims = gl_WorkGroupSize.xy * gl_NumWorkGroups.xy;
-    vec2 uv = (2.0 * gl_GlobalInvocationID.xy) / dims - vec2(1.0);
-
-    float z = depth * 2.0 - 1.0;
-    vec4 clipSpacePosition = vec4(uv, depth, 1.0);
-    vec4 viewSpacePosition = pushConsts.projInv * clipSpacePosition;
-    viewSpacePosition /= viewSpacePosition.w;
-
-    vec4 worldSpacePosition = pushConsts.viewInv * viewSpacePosition;
+    vec2 pixel = vec2(pixelPos) + 0.5;
 
-    vec3 rastPos = worldSpacePosition.xyz;
+    // Lengths are measured from where the pixel's ray starts on the near plane
+    vec3 rastPos = unproject(pixel, depth);
+    vec3 rayOrigin = unproject(pixel, 0.0);
 
-    float rastLength = length(rastPos - pushConsts.pos);
+    // the depth buffer is cleared to 1.0
+    float rastLength = (depth < 1.0)? length(rastPos - rayOrigin) : NO_RASTER_LENGTH;
 
     imageStore(outputDepth, pixelPos, vec4(rastLength));
-}
\ No newline at end of file
+}
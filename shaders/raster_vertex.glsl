@@ -2,11 +2,13 @@
 
 layout(location = 0) in vec3 position;
 layout(location = 1) in vec3 color;
+// per instance, from the mesh's space to the space the camera's view is in
+layout(location = 2) in mat4 model;
 
 layout(location = 0) out vec3 fragColor;
 
 layout(push_constant) uniform SpaceMatrices {
-    mat4 pvm;
+    mat4 projectionView;
 } space;
 
 // layout(binding = 0) uniform SpaceMatrices
@@ -16,7 +18,7 @@ layout(push_constant) uniform SpaceMatrices {
 
 void main() {
     // gl_Position = (space.projection * (space.view * space.model)) * vec4(position, 1.0);
-    gl_Position = space.pvm * vec4(position, 1.0);
+    gl_Position = space.projectionView * model * vec4(position, 1.0);
     fragColor = color;
 
 
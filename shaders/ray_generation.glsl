@@ -3,15 +3,16 @@
 
 layout (local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
 
-layout(push_constant) uniform Orient
+layout(push_constant) uniform PushConsts
 {
-    vec3 hDir;
-    vec3 vDir;
-    vec3 fBasis;
+    // (pixel x, pixel y, depth, 1) to a position relative to the camera, see CameraParameters::pixel_to_camera
+    mat4 pixelToCamera;
     int frameIndex;
-} orient;
+} pushConsts;
 
 layout (binding = 0, rgba16_snorm) uniform writeonly image2D rayDirections;
+// relative to the camera's position, on the near plane
+layout (binding = 1, rgba32f) uniform writeonly image2D rayOrigins;
 
 // Used for subpixel jitter
 vec2 halton (int index)
@@ -28,23 +29,26 @@ vec2 halton (int index)
     return a.zw;
 }
 
+vec3 unproject(vec2 pixel, float depth)
+{
+    vec4 position = pushConsts.pixelToCamera * vec4(pixel, depth, 1.0);
+    return position.xyz / position.w;
+}
+
 
 void main()
 {
-    vec3 rayDirection = vec3(1.0);
+    ivec2 pixelPos = ivec2(gl_GlobalInvocationID.xy);
 
-    vec2 dims = gl_WorkGroupSize.xy * gl_NumWorkGroups.xy;
+    // A different sub-pixel offset every frame, the taa resolve pass blends them together
+    vec2 subpixel_displacement = halton(pushConsts.frameIndex + 1) - 0.5;
 
-    vec2 subpixel_displacement = halton(orient.frameIndex + 1) - 0.5;
-    subpixel_displacement = vec2(0.0);
-    vec2 uv = ((2.0 * (gl_GlobalInvocationID.xy + subpixel_displacement)) - dims) / dims.y;
+    vec2 pixel = vec2(pixelPos) + 0.5 + subpixel_displacement;
 
+    // The same projection as the raster, so both halves line up
+    vec3 nearPoint = unproject(pixel, 0.0);
+    vec3 farPoint = unproject(pixel, 1.0);
 
-    ivec2 store_pos = ivec2(gl_GlobalInvocationID.x, dims.y - gl_GlobalInvocationID.y);
-
-    imageStore(
-        rayDirections, 
-        ivec2(store_pos),
-        vec4(normalize(uv.x * orient.hDir + uv.y * orient.vDir + orient.fBasis), 0.0)
-    );
-}
\ No newline at end of file
+    imageStore(rayDirections, pixelPos, vec4(normalize(farPoint - nearPoint), 0.0));
+    imageStore(rayOrigins, pixelPos, vec4(nearPoint, 0.0));
+}
@@ -0,0 +1,70 @@
+#version 460 core
+
+layout (local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+
+layout(set = 0, binding = 0, r11f_g11f_b10f) uniform readonly image2D currentColor;
+layout(set = 0, binding = 1, rgba16_snorm) uniform readonly image2D rayDirections;
+// the length of the ray to the surface seen through the pixel
+layout(set = 0, binding = 2, r32f) uniform readonly image2D depth;
+layout(set = 0, binding = 3) uniform sampler2D historyColor;
+layout(set = 0, binding = 4, r11f_g11f_b10f) uniform writeonly image2D resolvedColor;
+// relative to the current camera's position
+layout(set = 0, binding = 5, rgba32f) uniform readonly image2D rayOrigins;
+
+layout(push_constant) uniform PushConsts {
+    // positions relative to the current camera to the previous frame's pixels,
+    // see CameraParameters::camera_to_pixel
+    mat4 prevCameraToPixel;
+    vec2 renderDims;
+    uint hasHistory; // 0 when there is no previous frame to blend with
+    float currentWeight; // how much of the current frame goes into the history
+} pushConsts;
+
+
+void main()
+{
+    ivec2 pixelPos = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 dims = ivec2(gl_WorkGroupSize.xy * gl_NumWorkGroups.xy);
+
+    vec3 color = imageLoad(currentColor, pixelPos).rgb;
+
+    // History outside of the colors around the pixel is from something that isn't there anymore
+    vec3 neighborMin = color;
+    vec3 neighborMax = color;
+
+    for (int x = -1; x <= 1; x++)
+    {
+        for (int y = -1; y <= 1; y++)
+        {
+            vec3 neighbor = imageLoad(currentColor, clamp(pixelPos + ivec2(x, y), ivec2(0), dims - 1)).rgb;
+
+            neighborMin = min(neighborMin, neighbor);
+            neighborMax = max(neighborMax, neighbor);
+        }
+    }
+
+    // Where the previous camera saw the pixel's surface
+    vec3 rayOrigin = imageLoad(rayOrigins, pixelPos).xyz;
+    vec3 rayDir = imageLoad(rayDirections, pixelPos).xyz;
+    float rayLength = imageLoad(depth, pixelPos).r;
+
+    vec4 prevClip = pushConsts.prevCameraToPixel * vec4(rayOrigin + rayDir * rayLength, 1.0);
+    vec2 prevPixel = prevClip.xy / prevClip.w;
+
+    bool onScreen =
+        prevClip.w > 0.0
+        && all(greaterThanEqual(prevPixel, vec2(0.0)))
+        && all(lessThan(prevPixel, pushConsts.renderDims));
+
+    vec3 resolved = color;
+
+    if (pushConsts.hasHistory != 0 && onScreen)
+    {
+        vec3 history = texture(historyColor, prevPixel / vec2(textureSize(historyColor, 0))).rgb;
+
+        resolved = mix(clamp(history, neighborMin, neighborMax), color, pushConsts.currentWeight);
+    }
+
+    imageStore(resolvedColor, pixelPos, vec4(resolved, 1.0));
+}
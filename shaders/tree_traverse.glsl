@@ -8,26 +8,100 @@ layout(set = 0, binding = 0, r32ui) uniform readonly uimage1DArray treeArray;
 
 layout(set = 1, binding = 0, rgba16_snorm) uniform readonly image2D rayDirections;
 layout(set = 1, binding = 1, r11f_g11f_b10f) uniform writeonly image2D outputColor;
+// in: the length of the rays to rasterized entities, out: the length to the visible surface
 layout(set = 1, binding = 2, r32f) uniform image2D depth;
+layout(set = 1, binding = 3) uniform sampler2D rasterColor;
+// where the rays start, relative to the camera's position
+layout(set = 1, binding = 4, rgba32f) uniform readonly image2D rayOrigins;
+// Summed over the camera rays when collectStats is set, see TraversalStats in vox_drawer.rs
+layout(set = 1, binding = 5) buffer TraversalStats
+{
+    uint totalIterations;
+    uint cappedRays;
+    uint maxDepth;
+    uint hits;
+    uint misses;
+} stats;
 
 
 layout(set = 2, binding = 0) uniform usampler3D paletteVolumeAtlas;
 layout(set = 2, binding = 1) uniform sampler1DArray rgbPalettes;
+// texel i => emission, transparency, roughness, metal
+// texel i + 256 => index of refraction
+layout(set = 2, binding = 2) uniform sampler1DArray materialPalettes;
+// xyz => the voxel's prefab space normal, w => 1 on the surface, 0 inside
+layout(set = 2, binding = 3) uniform sampler3D normalVolumeAtlas;
 
 layout(push_constant) uniform PushConsts {
     vec3 pos;
     uint chunkCount;
+    uint prefabCount;
+    float pixelAngle; // the angle a perspective pixel covers
+    uint gridDegree; // the degree of the world grid tree, see Map::world_grid_tree
+    uint chunkDegree; // chunks are 2^chunkDegree blocks wide
+    vec3 sunDir; // towards the sun, normalized
+    float sunIntensity;
+    vec3 skyColor; // linear, drawn where rays miss
+    float ambientIntensity; // how much the sky lights every surface, shadowed or not
+    int frameIndex; // picks the ambient occlusion samples, see ray_generation.glsl
+    float aoRadius; // how far in voxels ambient occlusion rays go, 0 turns it off
+    float pixelSize; // the width in voxels of an orthographic pixel, prefab lods are off when both are 0
+    uint debugView; // see DebugView in vox_drawer.rs
+    uint collectStats; // 1 to add the camera rays to the stats buffer
 } pushConsts;
 
+// The order of DebugView in vox_drawer.rs
+const uint DEBUG_LIT = 0;
+const uint DEBUG_ALBEDO = 1;
+const uint DEBUG_DEPTH = 2;
+const uint DEBUG_NORMAL = 3;
+const uint DEBUG_ITERATIONS = 4;
+const uint DEBUG_CHUNK_INDEX = 5;
+const uint DEBUG_PREFAB = 6;
+const uint DEBUG_LAYER = 7;
+
 const uint NULL_INDEX = 100000000; // hundred million
 
+// Rays give up after this many steps
+const int MAX_ITERATIONS = 500;
+
+// Rays without a distance limit
+const float NO_MAX_DISTANCE = 1e30;
+
+// Short rays per pixel and frame for ambient occlusion,
+// the sample pattern moves every frame so the noise averages out
+const int AO_RAY_COUNT = 3;
+
+const float PI = 3.14159265;
+
+// world grid trees come before the chunk and prefab layers
+const uint TOP_TREE_NUM = 4;
+const uint GRID_TREE_LAYER = 0;
+
 
 struct RayResult
 {
     uint flag;
-    uint nodeValue;
+    uint nodeValue; // the prefab index of the hit voxel
+    uint orientation; // the orientation of the hit voxel's block
+    uint lodPaletteIndex; // NULL_INDEX unless the ray stopped at a node bigger than a voxel
     vec3 end;
+    vec3 normal; // the normal of the face the ray entered the hit node through
     ivec3 nodePos;
+    // For debug views, see traversalStats
+    uint iterations;
+    uint maxDepth; // the deepest the stack got
+    uint chunkIndex; // NULL_INDEX outside of chunks
+    uint layer; // the tree layer the ray ended in
+};
+
+struct VoxelMaterial
+{
+    float emission;
+    float transparency;
+    float roughness;
+    float metal;
+    float ior;
 };
 
 struct RayNodeQuery
@@ -181,16 +255,106 @@ mat3 tFromRayNode(ivec3 cornerStart, ivec3 cornerEnd, vec3 start, mat4 tTransfor
 }
 
 
-const int TREE_DEGREE = 9;
+// Must match PERMUTATIONS in block_state.rs
+const ivec3 PERMUTATIONS[6] =
+{
+    ivec3(0, 1, 2),
+    ivec3(1, 2, 0),
+    ivec3(2, 0, 1),
+    ivec3(0, 2, 1),
+    ivec3(2, 1, 0),
+    ivec3(1, 0, 2)
+};
+
+// The inverse of BlockOrientation::to_block_coords
+ivec3 toPrefabCoords(ivec3 blockCoords, uint orientation, int width)
+{
+    ivec3 axes = PERMUTATIONS[orientation >> 3];
+    uint flips = orientation & 7;
+
+    ivec3 prefabCoords;
+
+    for (int blockAxis = 0; blockAxis < 3; blockAxis++)
+    {
+        int c = blockCoords[blockAxis];
+
+        if (((flips >> blockAxis) & 1) == 1)
+        {
+            c = width - 1 - c;
+        }
+
+        prefabCoords[axes[blockAxis]] = c;
+    }
+
+    return prefabCoords;
+}
+
+
+// The inverse of toPrefabCoords for a direction
+vec3 toBlockDirection(vec3 prefabDir, uint orientation)
+{
+    ivec3 axes = PERMUTATIONS[orientation >> 3];
+    uint flips = orientation & 7;
+
+    vec3 blockDir;
+
+    for (int blockAxis = 0; blockAxis < 3; blockAxis++)
+    {
+        float c = prefabDir[axes[blockAxis]];
+
+        blockDir[blockAxis] = (((flips >> blockAxis) & 1) == 1)? -c : c;
+    }
+
+    return blockDir;
+}
+
+
+// A ray goes through the world grid tree, then a chunk tree, then a prefab tree
+const uint PREFAB_DEGREE = 5;
+
+// Must match MAX_TREE_DEGREE in map.rs
+const int MAX_TREE_DEGREE = 16;
+
+// The palette index standing in for a whole prefab
+uint prefabLodPalette(uint prefabIndex)
+{
+    uint rootLayer = TOP_TREE_NUM + pushConsts.chunkCount + prefabIndex;
+
+    return bitfieldExtract(imageLoad(treeArray, ivec2(0, rootLayer)).x, 24, 8);
+}
+
+// tEnter is the lower bound (t[0]) of the hit node,
+// its biggest component is the axis the ray last crossed to get into the node
+RayResult hitResult(vec3 edgeStart, vec3 dir, vec3 tEnter, 
+    uint prefabIndex, uint orientation, uint lodPaletteIndex, ivec3 nodePos)
+{
+    int axis = imaxComp(tEnter);
+
+    vec3 normal = vec3(0.0);
+    normal[axis] = -sign(dir[axis]);
 
-const int PREFAB_STOP_DEGREE = 4;
+    return RayResult(1, prefabIndex, orientation, lodPaletteIndex, 
+        edgeStart + maxComp(tEnter) * dir, normal, nodePos, 0, 0, NULL_INDEX, GRID_TREE_LAYER);
+}
+
+// Records how the traversal went in the result
+RayResult traversalStats(RayResult result, int iterations, uint maxDepth, uint chunkLayer, uint layer)
+{
+    result.iterations = uint(iterations);
+    result.maxDepth = maxDepth;
+    result.chunkIndex = (chunkLayer >= TOP_TREE_NUM)? chunkLayer - TOP_TREE_NUM : NULL_INDEX;
+    result.layer = layer;
+
+    return result;
+}
 
 
 // An implementation of efficient parametric octree intersection
-RayResult traverse(vec3 ro, vec3 dir)
+// Nothing further than maxDistance from ro is hit
+RayResult traverse(vec3 ro, vec3 dir, float maxDistance)
 {
     // A default result is prepared
-    RayResult result = RayResult(0, NULL_INDEX, ro, ivec3(0));
+    RayResult result = RayResult(0, NULL_INDEX, 0, NULL_INDEX, ro, vec3(0.0), ivec3(0), 0, 0, NULL_INDEX, GRID_TREE_LAYER);
 
     vec3 invDir = 1.0 / dir; // used for parameterization calculations
     ivec3 signDir = ivec3(sign(dir));
@@ -201,8 +365,13 @@ RayResult traverse(vec3 ro, vec3 dir)
     ivec3 cornerStartDir = (-signDir + ivec3(1)) / int(2);
     ivec3 cornerEndDir = (signDir + ivec3(1)) / int(2);
 
-    ivec3 cornerStart = treePos + cornerStartDir << TREE_DEGREE;
-    ivec3 cornerEnd = treePos + cornerEndDir << TREE_DEGREE;
+    // Depths where the ray moves into a chunk tree and a prefab tree
+    uint chunkStart = pushConsts.gridDegree;
+    uint prefabStart = chunkStart + pushConsts.chunkDegree;
+    uint treeDegree = prefabStart + PREFAB_DEGREE;
+
+    ivec3 cornerStart = treePos + cornerStartDir << treeDegree;
+    ivec3 cornerEnd = treePos + cornerEndDir << treeDegree;
 
 
     // The point on the surface of the octree where the parameterization starts
@@ -223,13 +392,22 @@ RayResult traverse(vec3 ro, vec3 dir)
     // based on ray orientation
     int orientMask[8] = orientMask(signDir);
 
-    RayNodeQuery stack[8];
+    RayNodeQuery stack[MAX_TREE_DEGREE];
     stack[0] = RayNodeQuery(bitfieldInsert(0, getFirstChild(t), 28, 4), ivec3(0));
 
     uint depth = 0;
-    uint layer = 4;
-
-    for (int iter = 0; iter < 500; iter++)
+    uint maxDepth = 0;
+    uint layer = GRID_TREE_LAYER;
+    uint chunkLayer = 0;
+    uint chunkLod = 0;
+    uint prefabIndex = NULL_INDEX;
+    uint orientation = 0;
+
+    // Blocks can be turned, so a block space octant
+    // is mapped to the prefab tree's octant while in a prefab tree
+    uint prefabOctants[8];
+
+    for (int iter = 0; iter < MAX_ITERATIONS; iter++)
     {
         // Extracted values from packed nodeAndOctant variable
         uint cdIndex = bitfieldExtract(stack[depth].nodeAndOctant, 0, 28);
@@ -249,6 +427,8 @@ RayResult traverse(vec3 ro, vec3 dir)
 
             mat3 tChild = tValuesForOctant(octant, t);
 
+            uint treeOctant = (depth >= prefabStart)? prefabOctants[orientedOctant] : orientedOctant;
+
             // The stack is updated to use the next intersected octant for a future iteration
             stack[depth].nodeAndOctant = 
                 bitfieldInsert(
@@ -257,35 +437,112 @@ RayResult traverse(vec3 ro, vec3 dir)
                     28, 4
                 );
             
-            if (((validMask >> orientedOctant) & 1) == 0
+            if (((validMask >> treeOctant) & 1) == 0
                 || minComp(tChild[2]) <= tRO)
             {
                 octant = bitfieldExtract(stack[depth].nodeAndOctant, 28, 4);
                 continue;
             }
 
-            uint nextCDIndex = firstChildIndex | orientedOctant;
-            if (depth == PREFAB_STOP_DEGREE - 1)
+            // Children are met front to back, so the rest are further away
+            if (maxComp(tChild[0]) - tRO > maxDistance)
             {
-                layer = 4 + pushConsts.chunkCount;
-                nextCDIndex = 0;
+                return traversalStats(result, iter, maxDepth, chunkLayer, layer);
             }
 
+            uint nextCDIndex = firstChildIndex | treeOctant;
+
             ivec3 childNodePos = 
                 stack[depth].nodePos 
-                + (OCTANT_POS_LOOKUP_TABLE[orientedOctant] << (TREE_DEGREE - depth - 1));
+                + (OCTANT_POS_LOOKUP_TABLE[orientedOctant] << (treeDegree - depth - 1));
+
+            if (depth == chunkStart - 1)
+            {
+                // The grid leaf stores the chunk index and the chunk's lod level
+                uint chunkValue = bitfieldExtract(imageLoad(treeArray, ivec2(nextCDIndex, layer)).x, 0, 16);
+
+                uint chunkIndex = bitfieldExtract(chunkValue, 0, 12);
+
+                if (chunkIndex >= pushConsts.chunkCount)
+                {
+                    octant = bitfieldExtract(stack[depth].nodeAndOctant, 28, 4);
+                    continue;
+                }
+
+                chunkLod = bitfieldExtract(chunkValue, 12, 4);
+                chunkLayer = TOP_TREE_NUM + chunkIndex;
+
+                layer = chunkLayer;
+                nextCDIndex = 0;
+            }
+
+            if (depth == prefabStart - 1)
+            {
+                // The chunk leaf stores the block value (see BlockState::to_value),
+                // which selects the prefab tree and its orientation
+                uint blockValue = bitfieldExtract(imageLoad(treeArray, ivec2(nextCDIndex, layer)).x, 0, 16);
+
+                uint blockPrefab = bitfieldExtract(blockValue, 0, 8);
+
+                if (blockPrefab >= pushConsts.prefabCount)
+                {
+                    octant = bitfieldExtract(stack[depth].nodeAndOctant, 28, 4);
+                    continue;
+                }
+
+                prefabIndex = blockPrefab;
+                orientation = bitfieldExtract(blockValue, 8, 8);
+
+                // Chunks past the first lod ring draw a voxel per block
+                if (chunkLod == 1)
+                {
+                    return traversalStats(hitResult(edgeStart, dir, tChild[0], 
+                        prefabIndex, orientation, prefabLodPalette(prefabIndex), childNodePos), iter, maxDepth, chunkLayer, layer);
+                }
+
+                for (int i = 0; i < 8; i++)
+                {
+                    ivec3 prefabOctantPos = toPrefabCoords(OCTANT_POS_LOOKUP_TABLE[i], orientation, 2);
+                    prefabOctants[i] = uint(prefabOctantPos.x + prefabOctantPos.y * 2 + prefabOctantPos.z * 4);
+                }
+
+                layer = TOP_TREE_NUM + pushConsts.chunkCount + prefabIndex;
+                nextCDIndex = 0;
+            }
+
+            // Further chunks stop in the chunk tree, a node is drawn as its most common block
+            if (chunkLod >= 2 && depth + 1 == prefabStart - min(chunkLod - 1, pushConsts.chunkDegree))
+            {
+                uint lodBlock = bitfieldExtract(imageLoad(treeArray, ivec2(nextCDIndex, layer)).x, 24, 8);
+
+                if (lodBlock < pushConsts.prefabCount)
+                {
+                    return traversalStats(hitResult(edgeStart, dir, tChild[0], 
+                        lodBlock, 0, prefabLodPalette(lodBlock), childNodePos), iter, maxDepth, chunkLayer, layer);
+                }
+            }
+
+            // A prefab node smaller than a pixel is drawn with its most common palette index
+            // (the top byte, see SESVOctree::build_lod) instead of going down to its voxels
+            float childWidth = float(1 << (treeDegree - depth - 1));
+            if (depth >= prefabStart - 1 && depth != treeDegree - 1
+                && childWidth < (maxComp(tChild[0]) - tRO) * pushConsts.pixelAngle + pushConsts.pixelSize)
+            {
+                return traversalStats(hitResult(edgeStart, dir, tChild[0], 
+                    prefabIndex, orientation, 
+                    bitfieldExtract(imageLoad(treeArray, ivec2(nextCDIndex, layer)).x, 24, 8),
+                    childNodePos), iter, maxDepth, chunkLayer, layer);
+            }
 
-            if (depth == TREE_DEGREE - 1)
+            if (depth == treeDegree - 1)
             {
-                result.end = edgeStart + (maxComp(tChild[0]) * dir);
-                result.nodeValue = 1;
-                result.flag = 1;
-                result.nodePos = childNodePos;
-                return result;
+                return traversalStats(hitResult(edgeStart, dir, tChild[0], 
+                    prefabIndex, orientation, NULL_INDEX, childNodePos), iter, maxDepth, chunkLayer, layer);
             }
 
 
             depth++;
+            maxDepth = max(maxDepth, depth);
             stack[depth].nodeAndOctant =
                 bitfieldInsert(
                     nextCDIndex, 
@@ -305,24 +562,115 @@ RayResult traverse(vec3 ro, vec3 dir)
         {
             if (depth == 0)
             {
-                return result;
+                return traversalStats(result, iter, maxDepth, chunkLayer, layer);
             }
 
-            if (depth == PREFAB_STOP_DEGREE)
+            if (depth == prefabStart)
             {
-                layer = 4;
+                layer = chunkLayer;
+            }
+            else if (depth == chunkStart)
+            {
+                layer = GRID_TREE_LAYER;
             }
 
             depth--;
-            cornerStart = stack[depth].nodePos + (cornerStartDir << (TREE_DEGREE - depth));
-            cornerEnd = stack[depth].nodePos + (cornerEndDir << (TREE_DEGREE - depth));
+            cornerStart = stack[depth].nodePos + (cornerStartDir << (treeDegree - depth));
+            cornerEnd = stack[depth].nodePos + (cornerEndDir << (treeDegree - depth));
             t = tFromRayNode(cornerStart, cornerEnd, edgeStart, tTransform);
         }
     }
 
-    return result;
+    return traversalStats(result, MAX_ITERATIONS, maxDepth, chunkLayer, layer);
+}
+
+
+VoxelMaterial fetchMaterial(uint paletteIndex, uint prefabIndex)
+{
+    vec4 properties = texelFetch(materialPalettes, ivec2(paletteIndex, prefabIndex), 0);
+    float ior = texelFetch(materialPalettes, ivec2(paletteIndex + 256, prefabIndex), 0).r;
+
+    return VoxelMaterial(properties.r, properties.g, properties.b, properties.a, ior);
+}
+
+// The smoothed normal of a voxel, turned into the block's orientation
+vec3 fetchNormal(ivec3 atlasPos, uint orientation)
+{
+    vec3 prefabNormal = texelFetch(normalVolumeAtlas, atlasPos, 0).xyz;
+
+    return normalize(toBlockDirection(prefabNormal, orientation));
+}
+
+
+// Same sequence as the sub-pixel jitter in ray_generation.glsl
+vec2 halton(int index)
+{
+    const vec2 coprimes = vec2(2.0, 3.0);
+    vec2 s = vec2(index, index);
+    vec4 a = vec4(1,1,0,0);
+    while (s.x > 0. && s.y > 0.)
+    {
+        a.xy = a.xy/coprimes;
+        a.zw += a.xy*mod(s, coprimes);
+        s = floor(s/coprimes);
+    }
+    return a.zw;
+}
+
+// Interleaved gradient noise, offsets the samples per pixel
+float gradientNoise(vec2 pixelPos)
+{
+    return fract(52.9829189 * fract(dot(pixelPos, vec2(0.06711056, 0.00583715))));
+}
+
+// Directions around the normal, more of them close to the normal
+vec3 cosineHemisphere(vec3 normal, vec2 rand)
+{
+    vec3 tangent = normalize(cross((abs(normal.y) < 0.99)? vec3(0.0, 1.0, 0.0) : vec3(1.0, 0.0, 0.0), normal));
+    vec3 bitangent = cross(normal, tangent);
+
+    float phi = 2.0 * PI * rand.x;
+    float r = sqrt(rand.y);
+
+    return normalize(tangent * cos(phi) * r + bitangent * sin(phi) * r + normal * sqrt(1.0 - rand.y));
+}
+
+// The share of short rays from the surface that get out without hitting anything
+float ambientOcclusion(vec3 origin, vec3 normal, ivec2 pixelPos)
+{
+    if (pushConsts.aoRadius <= 0.0)
+    {
+        return 1.0;
+    }
+
+    vec2 rotation = vec2(gradientNoise(vec2(pixelPos)), gradientNoise(vec2(pixelPos.yx) + vec2(17.0, 37.0)));
+
+    float open = 0.0;
+
+    for (int i = 0; i < AO_RAY_COUNT; i++)
+    {
+        vec2 rand = fract(halton(pushConsts.frameIndex * AO_RAY_COUNT + i + 1) + rotation);
+
+        if (traverse(origin, cosineHemisphere(normal, rand), pushConsts.aoRadius).flag == 0)
+        {
+            open += 1.0;
+        }
+    }
+
+    return open / float(AO_RAY_COUNT);
+}
+
+// Blue for 0, green for 0.5, red for 1
+vec3 heatmap(float value)
+{
+    return clamp(vec3(2.0 * value - 1.0, 1.0 - abs(2.0 * value - 1.0), 1.0 - 2.0 * value), 0.0, 1.0);
 }
 
+// A color per index, neighboring indices get unrelated colors
+vec3 indexColor(uint index)
+{
+    return fract(sin(vec3(float(index)) * vec3(12.9898, 78.233, 37.719)) * 43758.5453);
+}
 
 void main()
 {
@@ -331,37 +679,115 @@ void main()
 
     vec3 rayDir = imageLoad(rayDirections, pixelPos).xyz;
 
-    vec4 outColor = vec4(rayDir, 1.0);
+    vec4 outColor = vec4(pushConsts.skyColor, 1.0);
 
     vec3 offset = vec3(16.0, 16.0, 16.0) * 0.0;
 
 
-    vec3 ro = (pushConsts.pos * (1 << (TREE_DEGREE - PREFAB_STOP_DEGREE)) + offset);
-    RayResult result = traverse(ro, rayDir);
+    // The viewer's chunk is in the middle of the grid tree
+    vec3 chunkOffset = vec3((1 << (pushConsts.gridDegree - 1)) << pushConsts.chunkDegree);
+
+    vec3 rayOrigin = imageLoad(rayOrigins, pixelPos).xyz;
+
+    vec3 ro = ((pushConsts.pos + rayOrigin + chunkOffset) * (1 << PREFAB_DEGREE) + offset);
+    // Rays stop at rasterized entities in front of the voxels,
+    // raster lengths are in blocks and the traversal is in voxels
+    RayResult result = traverse(ro, rayDir, rastLength * float(1 << PREFAB_DEGREE));
 
+    // In blocks, like the raster lengths from depth_to_length.glsl.
+    // Rays that miss everything are seen as infinitely far.
+    float rayLength = (result.flag == 1)? length(result.end - ro) / float(1 << PREFAB_DEGREE) : rastLength;
+
+    if (result.flag == 0 && rastLength < NO_MAX_DISTANCE)
+    {
+        outColor = vec4(texelFetch(rasterColor, pixelPos, 0).rgb, 1.0);
+    }
 
     if (result.flag == 1)
     {
-        float rayLength = length(result.end - ro) / 32.0;
+        // prefab palette volumes are stacked along z in the atlas
+        ivec3 prefabPos = toPrefabCoords(result.nodePos % 32, result.orientation, 32);
+        ivec3 atlasPos = prefabPos + ivec3(0, 0, 32 * result.nodeValue);
 
-        outColor = vec4(1.0 - exp(-rayLength / 8.0));
+        bool isLod = result.lodPaletteIndex != NULL_INDEX;
+
+        uint paletteIndex = isLod? result.lodPaletteIndex : texelFetch(paletteVolumeAtlas, atlasPos, 0).r;
 
         vec3 prefabColor =
             texelFetch(rgbPalettes, 
                 ivec2(
-                    texelFetch(paletteVolumeAtlas, result.nodePos % 32, 0).r, 
-                    0
+                    paletteIndex, 
+                    result.nodeValue
                 ), 0
             ).rgb;
 
-        outColor = vec4(pow(prefabColor, vec3(2.2)), 1.0);
+        VoxelMaterial material = fetchMaterial(paletteIndex, result.nodeValue);
 
-        // outColor.b *= result.nodeValue * 0.1;
-        //outColor = vec4(vec3(result.nodePos % 32) / 32.0, 1.0);
+        // The smooth voxel normal is used where it agrees with the hit face,
+        // lod nodes have no voxel normal
+        vec3 normal = result.normal;
+        if (!isLod)
+        {
+            vec3 voxelNormal = fetchNormal(atlasPos, result.orientation);
+
+            if (dot(voxelNormal, result.normal) > 0.0)
+            {
+                normal = voxelNormal;
+            }
+        }
+
+        // The shadow ray starts just off the hit face so it doesn't hit its own voxel
+        float sunVisibility = 0.0;
+        float lambert = max(dot(normal, pushConsts.sunDir), 0.0);
+
+        if (lambert > 0.0)
+        {
+            vec3 shadowOrigin = result.end + result.normal * 0.01;
+            sunVisibility = (traverse(shadowOrigin, pushConsts.sunDir, NO_MAX_DISTANCE).flag == 1)? 0.0 : 1.0;
+        }
+
+        // lod nodes are far enough away to go without
+        float occlusion = isLod? 1.0 : ambientOcclusion(result.end + result.normal * 0.01, result.normal, pixelPos);
+
+        vec3 albedo = pow(prefabColor, vec3(2.2));
+
+        vec3 light = 
+            pushConsts.sunIntensity * lambert * sunVisibility
+            + pushConsts.skyColor * pushConsts.ambientIntensity * occlusion;
+
+        // emissive voxels go past 1.0 in the hdr color buffer
+        outColor = vec4(albedo * (light + material.emission), 1.0);
+
+        switch (pushConsts.debugView)
+        {
+            case DEBUG_ALBEDO: outColor.rgb = albedo; break;
+            case DEBUG_NORMAL: outColor.rgb = normal * 0.5 + 0.5; break;
+            case DEBUG_CHUNK_INDEX: outColor.rgb = indexColor(result.chunkIndex); break;
+            case DEBUG_PREFAB: outColor.rgb = indexColor(result.nodeValue); break;
+            case DEBUG_LAYER: outColor.rgb = indexColor(result.layer); break;
+        }
     }
 
-    // ivec3 signDir = ivec3(sign(rayDir));
-    // outColor = vec4(vec3((-signDir + ivec3(1)) / int(2)), 1.0);
+    // These cover rays that miss too
+    if (pushConsts.debugView == DEBUG_DEPTH)
+    {
+        outColor.rgb = vec3(1.0 - exp(-rayLength / 8.0));
+    }
+    else if (pushConsts.debugView == DEBUG_ITERATIONS)
+    {
+        outColor.rgb = heatmap(float(result.iterations) / float(MAX_ITERATIONS));
+    }
 
     imageStore(outputColor, pixelPos, outColor);
+    imageStore(depth, pixelPos, vec4(rayLength));
+
+    // Only the camera rays are counted, not the shadow and occlusion rays
+    if (pushConsts.collectStats == 1)
+    {
+        atomicAdd(stats.totalIterations, result.iterations);
+        atomicAdd(stats.cappedRays, (result.iterations == uint(MAX_ITERATIONS))? 1u : 0u);
+        atomicMax(stats.maxDepth, result.maxDepth);
+        atomicAdd(stats.hits, result.flag);
+        atomicAdd(stats.misses, 1u - result.flag);
+    }
 }
\ No newline at end of file
@@ -11,27 +11,40 @@ use nalgebra as na;
 use na::{Vector3, Point3, UnitQuaternion, Unit};
 use super::input::{InputState, PressKey};
 // use super::world_engine::chunk::Chunk;
-use super::world_engine::map::Map;
+use super::world_engine::map::{Map, MapSettings};
 
 use super::ecs_user::{
     ChunkPositionComponent, 
     WorldGridCoordinateComponent,
-    VelocityComponent};
+    VelocityComponent,
+    MeshComponent};
 
 
 pub fn run(
     input_state : Arc<InputState>,
-    rw_cam_dir : Arc<RwLock<UnitQuaternion<f32>>>)
+    rw_cam_dir : Arc<RwLock<UnitQuaternion<f32>>>,
+    debug_view : vox_drawer::DebugView)
 {
     let event_loop = EventLoop::new();
-    let (render_ctx, win_ctx) = vk_renderer::vk_ctx_init(get_vk_app_info(), &event_loop);
+    let (render_ctx, mut win_ctx) = vk_renderer::vk_ctx_init(get_vk_app_info(), &event_loop);
 
-    let mut map = Map::new([0 ; 3].into(), 2);
+    let mut map = Map::new([0 ; 3].into(), MapSettings::default());
+
+    let mut chunk_exponent = map.chunk_exponent();
 
     let mut vox_drawer = vox_drawer::VoxDrawer::new(render_ctx.clone(), win_ctx.swapchain.format(), win_ctx.dims(), &map);
+    vox_drawer.debug_view = debug_view;
+
+    let camera_settings = vox_drawer::CameraSettings::default();
+
+    // The debug view changes once per press
+    let mut debug_view_pressed = false;
 
     let mut render_now = std::time::Instant::now();
 
+    // Set when the window changed size or the swapchain went out of date
+    let mut recreate_swapchain = false;
+
     // let start_instant = std::time::Instant::now();
 
     let universe = legion::world::Universe::new();
@@ -51,6 +64,22 @@ pub fn run(
 
     let player_entity = initial_entities[0].clone();
 
+    let cube_mesh =
+    {
+        let (vertices, indices) = vox_drawer::cube_mesh();
+        vox_drawer.register_mesh(vertices, indices)
+    };
+
+    // Where the hardcoded cube used to be drawn
+    world.insert(
+        (),
+        vec![(
+            ChunkPositionComponent {0: Point3::origin()},
+            WorldGridCoordinateComponent {0: Point3::origin()},
+            MeshComponent {0: cube_mesh},
+        )]
+    );
+
     (*world.get_component_mut::<ChunkPositionComponent>(player_entity).unwrap()).0 
         += Vector3::new(0.1, 0.1, 0.1) * 16.0;
 
@@ -69,14 +98,7 @@ pub fn run(
             },
             Event::WindowEvent {event: WindowEvent::Resized(_), ..} =>
             {
-                // win_ctx.update_swapchain(
-                //     win_ctx.surface.window().inner_size().into(),
-                // );
-                // win_ctx.update_dynamic_state_with_scale(res_scale);
-
-                // let new_dims = win_ctx.scaled_dims(res_scale);
-
-                // vox_drawer.update_dims(new_dims);
+                recreate_swapchain = true;
             },
             Event::DeviceEvent {event: DeviceEvent::Key(val), ..} =>
             {
@@ -105,6 +127,23 @@ pub fn run(
             {
                 use legion::query::{Write, IntoQuery};
 
+                if recreate_swapchain
+                {
+                    let window_dims : [u32 ; 2] = win_ctx.surface.window().inner_size().into();
+
+                    // A minimized window has nothing to draw to
+                    if window_dims.contains(&0) || !win_ctx.update_swapchain(window_dims)
+                    {
+                        return;
+                    }
+
+                    let dims = win_ctx.dims();
+                    win_ctx.update_dynamic_state_with_dims(dims);
+                    vox_drawer.update_dims(dims);
+
+                    recreate_swapchain = false;
+                }
+
                 win_ctx.surface.window()
                 .set_cursor_position(winit::dpi::PhysicalPosition{x: 200, y: 200}).unwrap();
 
@@ -148,12 +187,41 @@ pub fn run(
 
 
                 // super::move_engine::run(&mut player_pos, input_state.clone(), &dir, dt);
-                let player_chunk_pos = (*world.get_component::<ChunkPositionComponent>(player_entity).unwrap()).0.clone();
-                let player_world_grid_coords = (*world.get_component::<WorldGridCoordinateComponent>(player_entity).unwrap()).0.clone();
+                let mut player_chunk_pos = (*world.get_component::<ChunkPositionComponent>(player_entity).unwrap()).0.clone();
+                let mut player_world_grid_coords = (*world.get_component::<WorldGridCoordinateComponent>(player_entity).unwrap()).0.clone();
                 map.handle_events(*dir.axis().unwrap(), player_world_grid_coords, player_chunk_pos);
-                map.adapt_to_world_position((*world.get_component::<WorldGridCoordinateComponent>(player_entity).unwrap()).0.clone().coords.into());
+
+                // positions are in chunks, so they change along with the chunk size
+                if map.chunk_exponent() != chunk_exponent
+                {
+                    let rescale_query = <(Write<WorldGridCoordinateComponent>, Write<ChunkPositionComponent>)>::query();
+                    for (mut world_grid_coord, mut chunk_pos) in rescale_query.iter(&mut world)
+                    {
+                        let (new_world_grid_coord, new_chunk_pos) =
+                            super::world_engine::map::rescale_chunk_position(
+                                world_grid_coord.0, chunk_pos.0, chunk_exponent, map.chunk_exponent());
+
+                        world_grid_coord.0 = new_world_grid_coord;
+                        chunk_pos.0 = new_chunk_pos;
+                    }
+
+                    chunk_exponent = map.chunk_exponent();
+
+                    player_chunk_pos = (*world.get_component::<ChunkPositionComponent>(player_entity).unwrap()).0.clone();
+                    player_world_grid_coords = (*world.get_component::<WorldGridCoordinateComponent>(player_entity).unwrap()).0.clone();
+                }
+
+                map.adapt_to_world_position(player_world_grid_coords);
+                map.update_lod_levels(player_chunk_pos);
                 map.generate_next_chunk();
 
+                if input_state.pressed("debug_view") && !debug_view_pressed
+                {
+                    vox_drawer.debug_view = vox_drawer.debug_view.next();
+                    println!("debug view: {:?}", vox_drawer.debug_view);
+                }
+                debug_view_pressed = input_state.pressed("debug_view");
+
                 let camera_parameters =
                 {
                     vox_drawer::CameraParameters
@@ -161,12 +229,44 @@ pub fn run(
                         chunk_position: player_chunk_pos,
                         world_grid_coords: player_world_grid_coords,
                         orientation: dir,
+                        settings: camera_settings,
                     }
                 };
             
+                let entities : Vec<vox_drawer::EntityInstance> =
+                {
+                    use legion::query::{Read, IntoQuery};
+
+                    let mesh_query = <(Read<ChunkPositionComponent>, Read<WorldGridCoordinateComponent>, Read<MeshComponent>)>::query();
+                    mesh_query.iter_immutable(&world)
+                    .map(|(chunk_pos, world_grid_coord, mesh)|
+                        vox_drawer::EntityInstance
+                        {
+                            mesh: mesh.0,
+                            chunk_position: chunk_pos.0,
+                            world_grid_coords: world_grid_coord.0,
+                        }
+                    )
+                    .collect()
+                };
+            
                 let render_start_instance = std::time::Instant::now();
-                vox_drawer.render_frame(&win_ctx, camera_parameters, &mut map);
-                println!("ms: {}", render_start_instance.elapsed().as_secs_f32() * 1000.0);
+                recreate_swapchain = !vox_drawer.render_frame(&mut win_ctx, camera_parameters, &entities, &mut map);
+                let frame_ms = render_start_instance.elapsed().as_secs_f32() * 1000.0;
+
+                match vox_drawer.take_traversal_stats()
+                {
+                    Some(stats) =>
+                    {
+                        println!("ms: {} {}", frame_ms, stats);
+
+                        if let Some(timings) = vox_drawer.average_pass_timings()
+                        {
+                            println!("gpu ms: {}", timings);
+                        }
+                    }
+                    None => println!("ms: {}", frame_ms),
+                }
 
             },
             _ => ()
@@ -7,7 +7,7 @@ use vulkano::
     image::
     {SwapchainImage},
     swapchain::
-    {Surface, Swapchain},
+    {Surface, Swapchain, SwapchainCreationError},
     command_buffer::
     {DynamicState},
     framebuffer::
@@ -40,24 +40,31 @@ type RenderpassArced = Arc<dyn RenderPassAbstract + Send + Sync>;
 impl<W> VkWindowContext<W>
     where W : Send + Sync + 'static
 {
+    // Returns false when the surface doesn't support the dims,
+    // which happens while the window is being resized
     pub fn update_swapchain(
         &mut self, 
         dimensions : [u32 ; 2])
+        -> bool
     {
         // First updates the variables of the struct
         let res = match self.swapchain.recreate_with_dimensions(dimensions) {
             Ok(r) => r,
+            Err(SwapchainCreationError::UnsupportedDimensions) => return false,
             Err(err) => panic!("{:?}", err)
         };
         self.swapchain = res.0;
         self.images = res.1;
+
+        true
     }
 
     pub fn update_with_res_scale(
         &mut self,
         res_scale : [f32 ; 2])
+        -> bool
     {
-        self.update_swapchain(self.scaled_dims(res_scale));
+        self.update_swapchain(self.scaled_dims(res_scale))
     }
     
     pub fn framebuffers_init(
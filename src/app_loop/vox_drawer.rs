@@ -10,13 +10,12 @@ use vulkano::
     image::{AttachmentImage, ImageUsage, StorageImage, Dimensions},
     sampler::{Sampler, SamplerAddressMode, Filter, MipmapMode},    
     format::{Format},
-    sync::GpuFuture,
+    sync::{GpuFuture, FenceSignalFuture},
     command_buffer::{AutoCommandBuffer, AutoCommandBufferBuilder, DynamicState},
     
 };
 
 use nalgebra as na;
-use na::{Vector3};
 
 
 
@@ -28,9 +27,15 @@ pub struct ImgData
     raster_depth : Arc<AttachmentImage>,
 
     ray_directions : Arc<StorageImage<Format>>,
+    // Where rays start on the near plane, relative to the camera's position
+    ray_origins : Arc<StorageImage<Format>>,
     world_color : Arc<StorageImage<Format>>,
     world_depth : Arc<StorageImage<Format>>,
 
+    // world_color blended with the previous frames, then copied to the history for the next frame
+    taa_resolved : Arc<StorageImage<Format>>,
+    taa_history : Arc<StorageImage<Format>>,
+
     postprocessed : Arc<StorageImage<Format>>,
     swapchain_format : Format,
 
@@ -97,6 +102,33 @@ impl ImgData
                 compute_family_iter.clone(),
             ).unwrap();
 
+        let taa_resolved =
+            StorageImage::with_usage(
+                device.clone(),
+                Dimensions::Dim2d {width: dims[0], height: dims[1]},
+                vulkano::format::Format::B10G11R11UfloatPack32,
+                ImageUsage {storage: true, transfer_source: true, ..ImageUsage::none()},
+                compute_family_iter.clone()
+            ).unwrap();
+
+        let taa_history =
+            StorageImage::with_usage(
+                device.clone(),
+                Dimensions::Dim2d {width: dims[0], height: dims[1]},
+                vulkano::format::Format::B10G11R11UfloatPack32,
+                ImageUsage {sampled: true, transfer_destination: true, ..ImageUsage::none()},
+                compute_family_iter.clone()
+            ).unwrap();
+
+        let ray_origins =
+            StorageImage::with_usage(
+                device.clone(),
+                Dimensions::Dim2d {width: dims[0], height: dims[1]},
+                vulkano::format::Format::R32G32B32A32Sfloat,
+                ImageUsage {storage: true, ..ImageUsage::none()},
+                compute_family_iter.clone()
+            ).unwrap();
+
         let ray_directions =
             StorageImage::with_usage(
                 device.clone(),
@@ -132,7 +164,7 @@ impl ImgData
         
         ImgData
         {
-            swapchain_format, ray_directions, world_color, world_depth, postprocessed, raster_color, raster_depth, linear_sampler, nearest_sampler
+            swapchain_format, ray_directions, ray_origins, world_color, world_depth, taa_resolved, taa_history, postprocessed, raster_color, raster_depth, linear_sampler, nearest_sampler
         }
     }
 }
@@ -156,36 +188,433 @@ mod top_down_world_drawer;
 mod entity_drawer;
 use entity_drawer::EntityDrawer;
 
+mod mesh_registry;
+pub use mesh_registry::{MeshHandle, MeshVertex, cube_mesh};
+
 mod postprocess_drawer;
 use postprocess_drawer::PostprocessDrawer;
 
+mod taa_drawer;
+use taa_drawer::TaaDrawer;
+
+mod gpu_timer;
+use gpu_timer::GpuTimer;
+
 pub struct VoxDrawer
 {
     vk_ctx : Arc<VkRenderContext>,
 
     imgs : ImgData,
-    aspect_ratio : f32,
 
     tree_draw : top_down_world_drawer::WorldDrawer,
     entity_draw : EntityDrawer,
     post_process_draw : PostprocessDrawer,
+    taa_draw : TaaDrawer,
 
     vox_map_ctx : VoxMapContext,
 
-    prior_future_state : Option<Box<dyn GpuFuture>>,
+    // The frames the gpu may still be working on, see FRAMES_IN_FLIGHT
+    frame_slots : Vec<FrameSlot>,
+    // The next frame is chained after this one
+    last_frame_fence : Option<FrameFence>,
 
     frame_num : u32,
+
+    // The camera of the last frame and the chunk degree its position is in, for reprojection
+    previous_camera : Option<(CameraParameters, u32)>,
+
+    pub lighting : LightingSettings,
+    pub render_scale : RenderScaleController,
+    pub debug_view : DebugView,
+
+    // The gpu time of the last frame that was read back, in seconds
+    last_frame_time : Option<f32>,
+    last_frame_start : Option<std::time::Instant>,
+
+    // Traversal stats are collected every this many frames, never when None
+    pub traversal_stats_interval : Option<u32>,
+    last_traversal_stats : Option<TraversalStats>,
+
+    gpu_timer : GpuTimer,
+    pass_timings : Option<PassTimings>,
+    average_pass_timings : Option<PassTimings>,
+}
+
+// How many frames the cpu records ahead of the gpu before it waits.
+// Resources the cpu writes each frame have a copy per slot, the images only the gpu touches are shared
+// since the queue runs the frames in order.
+pub const FRAMES_IN_FLIGHT : usize = 2;
+
+type FrameFence = Arc<FenceSignalFuture<Box<dyn GpuFuture>>>;
+
+// A frame the gpu may still be working on
+struct FrameSlot
+{
+    fence : Option<FrameFence>,
+    // Whether the frame wrote the slot's traversal stats
+    collected_stats : bool,
 }
 
-#[derive(Debug)]
+// A mesh drawn at an entity's position
+#[derive(Clone, Copy, Debug)]
+pub struct EntityInstance
+{
+    pub mesh : MeshHandle,
+    pub chunk_position : na::Point3<f32>,
+    pub world_grid_coords : na::Point3<i32>,
+}
+
+#[derive(Clone, Copy, Debug)]
 pub struct CameraParameters
 {
     pub chunk_position : na::Point3<f32>,
     pub world_grid_coords : na::Point3<i32>,
     pub orientation : na::UnitQuaternion<f32>,
+    pub settings : CameraSettings,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection
+{
+    Perspective,
+    // Rays are parallel, height is how many blocks the screen's height covers
+    Orthographic {height : f32},
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct CameraSettings
+{
+    // Diagonal, in degrees. Only used by perspective projections.
+    pub fov : f32,
+    pub near : f32,
+    pub far : f32,
+    pub projection : Projection,
+}
+
+impl CameraSettings
+{
+    pub fn default()
+        -> CameraSettings
+    {
+        CameraSettings
+        {
+            fov : 150.0,
+            near : NEAR,
+            far : FAR,
+            projection : Projection::Perspective,
+        }
+    }
+
+    // The one projection every pass is derived from.
+    // Depth goes from 0 at the near plane to 1 at the far plane, like vulkan expects.
+    pub fn projection_matrix(&self, aspect_ratio : f32)
+        -> na::Matrix4<f32>
+    {
+        let gl_projection = match self.projection
+        {
+            Projection::Perspective =>
+                na::Perspective3::new(aspect_ratio, get_vertical_fov(self.fov, aspect_ratio), self.near, self.far)
+                .into_inner(),
+            Projection::Orthographic {height} =>
+                na::Orthographic3::new(
+                    -height * aspect_ratio / 2.0, height * aspect_ratio / 2.0,
+                    -height / 2.0, height / 2.0,
+                    self.near, self.far)
+                .into_inner(),
+        };
+
+        // nalgebra's projections put depth in [-1, 1]
+        let depth_range = na::Matrix4::new(
+            1.0, 0.0, 0.0, 0.0,
+            0.0, 1.0, 0.0, 0.0,
+            0.0, 0.0, 0.5, 0.5,
+            0.0, 0.0, 0.0, 1.0);
+
+        depth_range * gl_projection
+    }
+}
+
+impl CameraParameters
+{
+    // Looks from the eye along the camera's orientation
+    pub fn view(&self, eye : na::Point3<f32>)
+        -> na::Isometry3<f32>
+    {
+        let (_, v_axis, f_axis) = top_down_world_drawer::get_camera_orientation(self.orientation);
+
+        na::Isometry3::look_at_rh(&eye, &(eye + f_axis), &-v_axis)
+    }
+
+    // Maps positions relative to the camera to (pixel x, pixel y, depth, 1) once divided by w.
+    // Pixel centers are at half coordinates.
+    pub fn camera_to_pixel(&self, render_dims : [f32 ; 2])
+        -> na::Matrix4<f32>
+    {
+        let aspect_ratio = render_dims[0] / render_dims[1];
+
+        let ndc_to_pixel = na::Matrix4::new(
+            render_dims[0] / 2.0, 0.0, 0.0, render_dims[0] / 2.0,
+            0.0, render_dims[1] / 2.0, 0.0, render_dims[1] / 2.0,
+            0.0, 0.0, 1.0, 0.0,
+            0.0, 0.0, 0.0, 1.0);
+
+        ndc_to_pixel
+        * self.settings.projection_matrix(aspect_ratio)
+        * self.view(na::Point3::origin()).to_homogeneous()
+    }
+
+    pub fn pixel_to_camera(&self, render_dims : [f32 ; 2])
+        -> na::Matrix4<f32>
+    {
+        self.camera_to_pixel(render_dims).try_inverse().unwrap()
+    }
+}
+
+
+// Light colors are linear and go past 1.0, the monitor pass tonemaps them
+#[derive(Clone, Copy, Debug)]
+pub struct LightingSettings
+{
+    // The direction towards the sun, +y is up
+    pub sun_direction : na::Vector3<f32>,
+    pub sun_intensity : f32,
+    // Drawn where rays miss and used as ambient light
+    pub sky_color : na::Vector3<f32>,
+    pub ambient_intensity : f32,
+    // How far in voxels ambient occlusion looks for neighbors, 0 turns it off
+    pub ao_radius : f32,
+}
+
+impl LightingSettings
+{
+    pub fn default()
+        -> LightingSettings
+    {
+        LightingSettings
+        {
+            sun_direction : na::Vector3::new(0.4, 1.0, 0.3).normalize(),
+            sun_intensity : 3.0,
+            sky_color : na::Vector3::new(0.45, 0.6, 0.9),
+            ambient_intensity : 0.6,
+            ao_radius : 16.0,
+        }
+    }
+}
+
+
+// What the voxel pass draws instead of the lit color.
+// Must stay in the order of the DEBUG_ constants in tree_traverse.glsl.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DebugView
+{
+    Lit,
+    Albedo,
+    Depth,
+    Normal,
+    // A heatmap of traversal iterations, red at the cap
+    Iterations,
+    ChunkIndex,
+    PrefabId,
+    TreeLayer,
 }
 
+const DEBUG_VIEWS : [DebugView ; 8] =
+[
+    DebugView::Lit, DebugView::Albedo, DebugView::Depth, DebugView::Normal,
+    DebugView::Iterations, DebugView::ChunkIndex, DebugView::PrefabId, DebugView::TreeLayer,
+];
+
+impl DebugView
+{
+    // Cycles through the views, back to Lit after the last one
+    pub fn next(self)
+        -> DebugView
+    {
+        DEBUG_VIEWS[(self as usize + 1) % DEBUG_VIEWS.len()]
+    }
+
+    // The view with the name, ignoring case (e.g. "normal")
+    pub fn from_name(name : &str)
+        -> Option<DebugView>
+    {
+        DEBUG_VIEWS.iter().copied().find(|view| format!("{:?}", view).eq_ignore_ascii_case(name))
+    }
+}
+
+// Summed over the camera rays of one frame by tree_traverse.glsl.
+// Must stay in the order of the TraversalStats buffer there.
+#[derive(Clone, Copy, Debug)]
+pub struct TraversalStats
+{
+    pub total_iterations : u32,
+    // Rays that stopped at MAX_ITERATIONS
+    pub capped_rays : u32,
+    pub max_depth : u32,
+    pub hits : u32,
+    pub misses : u32,
+}
+
+impl std::fmt::Display for TraversalStats
+{
+    fn fmt(&self, f : &mut std::fmt::Formatter)
+        -> std::fmt::Result
+    {
+        let rays = self.hits + self.misses;
+
+        write!(
+            f, "rays: {} hits: {} misses: {} avg iterations: {:.1} capped: {} max depth: {}",
+            rays, self.hits, self.misses,
+            self.total_iterations as f32 / rays.max(1) as f32,
+            self.capped_rays, self.max_depth
+        )
+    }
+}
+
+// Gpu milliseconds spent in each pass of a frame, see GpuTimer
+#[derive(Clone, Copy, Debug)]
+pub struct PassTimings
+{
+    pub map_update : f32,
+    pub raster : f32,
+    pub depth_to_length : f32,
+    pub ray_gen : f32,
+    pub traverse : f32,
+    pub taa_resolve : f32,
+    pub post_process : f32,
+    pub blit : f32,
+}
 
+// The timestamps written in render_frame, between them are the passes and the wait for the swapchain image
+const TIMESTAMP_COUNT : u32 = 10;
+
+impl PassTimings
+{
+    // From the durations between render_frame's timestamps
+    fn from_durations(durations : &[f32])
+        -> PassTimings
+    {
+        PassTimings
+        {
+            map_update : durations[0],
+            raster : durations[1],
+            depth_to_length : durations[2],
+            ray_gen : durations[3],
+            traverse : durations[4],
+            taa_resolve : durations[5],
+            post_process : durations[6],
+            // durations[7] is the wait for the swapchain image
+            blit : durations[8],
+        }
+    }
+
+    pub fn total(&self)
+        -> f32
+    {
+        self.map_update + self.raster + self.depth_to_length + self.ray_gen
+        + self.traverse + self.taa_resolve + self.post_process + self.blit
+    }
+
+    // Moves each pass's time weight of the way to the other's
+    fn blend(&self, other : &PassTimings, weight : f32)
+        -> PassTimings
+    {
+        let mix = |a : f32, b : f32| a * (1.0 - weight) + b * weight;
+
+        PassTimings
+        {
+            map_update : mix(self.map_update, other.map_update),
+            raster : mix(self.raster, other.raster),
+            depth_to_length : mix(self.depth_to_length, other.depth_to_length),
+            ray_gen : mix(self.ray_gen, other.ray_gen),
+            traverse : mix(self.traverse, other.traverse),
+            taa_resolve : mix(self.taa_resolve, other.taa_resolve),
+            post_process : mix(self.post_process, other.post_process),
+            blit : mix(self.blit, other.blit),
+        }
+    }
+}
+
+impl std::fmt::Display for PassTimings
+{
+    fn fmt(&self, f : &mut std::fmt::Formatter)
+        -> std::fmt::Result
+    {
+        write!(
+            f, "total: {:.2} map: {:.2} raster: {:.2} depth: {:.2} ray gen: {:.2} traverse: {:.2} taa: {:.2} post: {:.2} blit: {:.2}",
+            self.total(), self.map_update, self.raster, self.depth_to_length, self.ray_gen,
+            self.traverse, self.taa_resolve, self.post_process, self.blit
+        )
+    }
+}
+
+// Picks the share of the window's resolution that is rendered,
+// the blit to the swapchain scales it back up
+#[derive(Clone, Copy, Debug)]
+pub struct RenderScaleController
+{
+    pub target_frame_time : f32,
+    pub min_scale : f32,
+    pub max_scale : f32,
+    // Used instead of the measured scale when set
+    pub manual_scale : Option<f32>,
+
+    scale : f32,
+    average_frame_time : Option<f32>,
+}
+
+// The scale moves in steps, every change throws away the taa history
+const RENDER_SCALE_STEP : f32 = 0.05;
+
+impl RenderScaleController
+{
+    pub fn new(target_frame_time : f32)
+        -> RenderScaleController
+    {
+        RenderScaleController
+        {
+            target_frame_time,
+            min_scale : 0.25,
+            max_scale : 1.0,
+            manual_scale : None,
+            scale : 1.0,
+            average_frame_time : None,
+        }
+    }
+
+    pub fn scale(&self)
+        -> f32
+    {
+        self.manual_scale.unwrap_or(self.scale).max(self.min_scale).min(self.max_scale)
+    }
+
+    pub fn update(&mut self, frame_time : f32)
+    {
+        let average = match self.average_frame_time
+        {
+            Some(average) => average * 0.9 + frame_time * 0.1,
+            None => frame_time,
+        };
+
+        // The cost goes with the pixel count, the square of the scale
+        let wanted_scale = 
+            (self.scale * (self.target_frame_time / average).sqrt())
+            .max(self.min_scale).min(self.max_scale);
+
+        if (wanted_scale - self.scale).abs() < RENDER_SCALE_STEP
+        {
+            self.average_frame_time = Some(average);
+            return;
+        }
+
+        self.scale = (wanted_scale / RENDER_SCALE_STEP).round() * RENDER_SCALE_STEP;
+
+        // Frame times from before the change don't tell much about the new scale
+        self.average_frame_time = None;
+    }
+}
+
+
+// The default near and far planes, see CameraSettings
 pub const NEAR : f32 = 0.001;
 pub const FAR : f32 = 200.0;
 
@@ -205,80 +634,151 @@ impl VoxDrawer
 
         let imgs = ImgData::new(dims, device.clone(), queue.clone(), swapchain_format);
 
-        // find a better way to store/recalculate this variable later...
-        let aspect_ratio = 
-            dims[0] as f32 / dims[1] as f32;
-        
         let entity_draw = EntityDrawer::new(vk_ctx.clone(), &imgs);
         let post_process_draw = PostprocessDrawer::new(vk_ctx.clone(), &imgs);
+        let taa_draw = TaaDrawer::new(vk_ctx.clone(), &imgs);
 
         let tree_draw = top_down_world_drawer::WorldDrawer::new(vk_ctx.clone(), &imgs);
 
         let vox_map_ctx = VoxMapContext::new(queue.clone(), map, &tree_draw.pipelines);
 
-        let prior_future_state = Some(Box::new(vulkano::sync::now(device.clone())) as Box<dyn GpuFuture>);
+        let gpu_timer = GpuTimer::new(queue.clone(), TIMESTAMP_COUNT);
+
+        let frame_slots = (0..FRAMES_IN_FLIGHT).map(|_| FrameSlot {fence : None, collected_stats : false}).collect();
 
         VoxDrawer {
             vk_ctx, 
             imgs,
-            aspect_ratio,
-            entity_draw, post_process_draw, tree_draw, 
+            entity_draw, post_process_draw, taa_draw, tree_draw, 
             vox_map_ctx, 
-            prior_future_state,
-            frame_num : 0
+            frame_slots,
+            last_frame_fence : None,
+            frame_num : 0,
+            previous_camera : None,
+            lighting : LightingSettings::default(),
+            render_scale : RenderScaleController::new(1.0 / 60.0),
+            debug_view : DebugView::Lit,
+            last_frame_time : None,
+            last_frame_start : None,
+            traversal_stats_interval : Some(60),
+            last_traversal_stats : None,
+            gpu_timer,
+            pass_timings : None,
+            average_pass_timings : None,
         }
     }
 
 
 
+    // Returns false when the swapchain doesn't fit the window anymore and has to be recreated
     pub fn render_frame<W>(&mut self, 
-        win_ctx : &VkWindowContext<W>, 
-        camera : CameraParameters, map : &mut Map)
+        win_ctx : &mut VkWindowContext<W>, 
+        camera : CameraParameters, entities : &[EntityInstance], map : &mut Map)
+        -> bool
         where W : Send + Sync + 'static
     {
         let device = self.vk_ctx.logical_device.clone();
         let queue = self.vk_ctx.queue.clone();
 
-        self.prior_future_state.as_mut().unwrap().cleanup_finished();
+        let slot = self.frame_num as usize % FRAMES_IN_FLIGHT;
+
+        let frame_start = std::time::Instant::now();
+        let frame_interval = self.last_frame_start.map(|start| (frame_start - start).as_secs_f32());
+        self.last_frame_start = Some(frame_start);
 
-        self.update_render_scale();
+        // The only wait on the gpu, for the frame that used this slot before
+        if let Some(fence) = self.frame_slots[slot].fence.take()
+        {
+            fence.wait(None).unwrap();
+        }
 
-        let (img_num, _, acquire_future) =
+        if self.frame_slots[slot].collected_stats
+        {
+            self.frame_slots[slot].collected_stats = false;
+            self.last_traversal_stats = Some(self.tree_draw.read_stats(slot));
+        }
+
+        match self.gpu_timer.read(slot)
+        {
+            Some(durations) =>
+            {
+                let timings = PassTimings::from_durations(&durations);
+
+                self.average_pass_timings = Some(
+                    match self.average_pass_timings
+                    {
+                        Some(average) => average.blend(&timings, 0.1),
+                        None => timings,
+                    }
+                );
+                self.pass_timings = Some(timings);
+                self.last_frame_time = Some(timings.total() / 1000.0);
+            }
+            // Without timestamps the time between frames stands in for the gpu's
+            None => self.last_frame_time = frame_interval,
+        }
+
+        self.update_render_scale(win_ctx);
+
+        let (img_num, suboptimal, acquire_future) =
             match vulkano::swapchain::acquire_next_image(win_ctx.swapchain.clone(), None)
             {
                 Ok(r) => r,
+                Err(vulkano::swapchain::AcquireError::OutOfDate) => return false,
                 Err(err) => panic!("{:?}", err)
             };
 
         let cmd_map_update = self.vox_map_ctx.update(queue.clone(), map);
 
-        let ratio = self.aspect_ratio;
-        let model_pos = 1.00 * Vector3::new(0.0f32, 0.6, 0.90);
-
         let cmd_raster_render = 
             self.entity_draw.cmd_buf_raster(
-                &win_ctx.dynamic_state, ratio, camera.orientation, camera.chunk_position, model_pos);
+                &win_ctx.dynamic_state, camera, entities, self.vox_map_ctx.chunk_degree());
             
 
         let cmd_depth_to_length =
             self.tree_draw.cmd_buf_depth_to_length(
                 &win_ctx.dynamic_state,
-                camera.orientation, 
-                camera.chunk_position
+                camera
             );
 
         let frame_index = ((self.frame_num % 64) + 1) as i32;
 
+        let collect_stats =
+            self.traversal_stats_interval
+            .map_or(false, |interval| self.frame_num % interval.max(1) == 0);
+
+        if collect_stats
+        {
+            self.tree_draw.reset_stats(slot);
+        }
+
         // let cmd_world_render =
         //     self.world_draw.cmd_buf_draw(
         //         &win_ctx.dynamic_state, &self.vox_map_ctx, camera, frame_index
         //     );
         
+        let cmd_ray_gen =
+            self.tree_draw.cmd_buf_ray_gen(
+                &win_ctx.dynamic_state, camera, frame_index
+            );
+
         let cmd_tree_render = 
             self.tree_draw.cmd_buf_draw(
-                &win_ctx.dynamic_state, &self.vox_map_ctx, camera, frame_index
+                &win_ctx.dynamic_state, &self.vox_map_ctx, camera, self.lighting, self.debug_view, collect_stats, slot, frame_index
             );
         
+        // The history is thrown away when chunk positions change scale
+        let chunk_degree = self.vox_map_ctx.chunk_degree();
+        let previous_camera =
+            self.previous_camera
+            .filter(|(_, previous_degree)| *previous_degree == chunk_degree)
+            .map(|(previous, _)| previous);
+
+        let cmd_taa_resolve =
+            self.taa_draw.cmd_resolve(
+                &win_ctx.dynamic_state, camera, previous_camera, chunk_degree
+            );
+
         let cmd_post_process =
             self.post_process_draw.cmd_post_process(
                 &win_ctx.dynamic_state,
@@ -290,50 +790,153 @@ impl VoxDrawer
                 win_ctx.images[img_num].clone()
             );
         
-        let future_one =
-            self.prior_future_state.take().unwrap()
+        // A timestamp after every pass, see PassTimings
+        let gpu_timer = &self.gpu_timer;
+        let timestamp = |index| gpu_timer.cmd_timestamp(slot, index);
+
+        // Chained after the last frame, so the gpu runs the frames in order without the cpu waiting in between
+        let previous_frame : Box<dyn GpuFuture> =
+            match self.last_frame_fence.clone()
+            {
+                Some(fence) => Box::new(fence),
+                None => Box::new(vulkano::sync::now(device.clone())),
+            };
+
+        // Meshes registered since the last frame are uploaded before it draws them
+        let previous_frame : Box<dyn GpuFuture> =
+            match self.entity_draw.meshes.take_uploads()
+            {
+                Some(uploads) => Box::new(previous_frame.join(uploads)),
+                None => previous_frame,
+            };
+
+        let passes =
+            previous_frame
+            .then_execute(queue.clone(), timestamp(0)).unwrap()
             .then_execute(queue.clone(), cmd_map_update).unwrap()
+            .then_execute(queue.clone(), timestamp(1)).unwrap()
             .then_execute(queue.clone(), cmd_raster_render).unwrap()
+            .then_execute(queue.clone(), timestamp(2)).unwrap()
             .then_execute(queue.clone(), cmd_depth_to_length).unwrap()
+            .then_execute(queue.clone(), timestamp(3)).unwrap()
             // .then_execute(queue.clone(), cmd_world_render).unwrap()
+            .then_execute(queue.clone(), cmd_ray_gen).unwrap()
+            .then_execute(queue.clone(), timestamp(4)).unwrap()
             .then_execute(queue.clone(), cmd_tree_render).unwrap()
-            .then_execute(queue.clone(), cmd_post_process).unwrap();
-
-        
-        future_one.then_signal_fence().wait(None).unwrap();
+            .then_execute(queue.clone(), timestamp(5)).unwrap()
+            .then_execute(queue.clone(), cmd_taa_resolve).unwrap()
+            .then_execute(queue.clone(), timestamp(6)).unwrap()
+            .then_execute(queue.clone(), cmd_post_process).unwrap()
+            .then_execute(queue.clone(), timestamp(7)).unwrap();
+
+        // Submitted on their own so only the blit waits for the swapchain image
+        passes.flush().unwrap();
+
+        let frame : Box<dyn GpuFuture> =
+            Box::new(
+                passes
+                .join(acquire_future)
+                .then_execute(queue.clone(), timestamp(8)).unwrap()
+                .then_execute(queue.clone(), cmd_swapchain_blit).unwrap()
+                .then_execute(queue.clone(), timestamp(9)).unwrap()
+                .then_swapchain_present(queue.clone(), win_ctx.swapchain.clone(), img_num)
+            );
 
-        let future_two =
-            vulkano::sync::now(device.clone())
-            .join(acquire_future)
-            .then_execute(queue.clone(), cmd_swapchain_blit).unwrap()
-            .then_swapchain_present(queue.clone(), win_ctx.swapchain.clone(), img_num)
-            .then_signal_fence_and_flush();
+        let mut swapchain_fits = !suboptimal;
 
-        match future_two
+        match frame.then_signal_fence_and_flush()
         {
-            Ok(future) =>
+            Ok(fence) =>
             {
-                self.prior_future_state = Some(Box::new(future));
+                let fence = Arc::new(fence);
+
+                self.gpu_timer.mark_submitted(slot);
+                self.frame_slots[slot] = FrameSlot {fence : Some(fence.clone()), collected_stats : collect_stats};
+                self.last_frame_fence = Some(fence);
+            }
+            Err(vulkano::sync::FlushError::OutOfDate) =>
+            {
+                // Only the present failed, the passes were submitted and waited for when the future was dropped
+                self.gpu_timer.mark_submitted(slot);
+                self.frame_slots[slot].collected_stats = collect_stats;
+                swapchain_fits = false;
             }
             Err(e) =>
             {
                 println!("{:?}", e);
-                self.prior_future_state = Some(Box::new(vulkano::sync::now(device.clone())));
             }
         }
 
+        self.previous_camera = Some((camera, chunk_degree));
         self.frame_num += 1;
+
+        swapchain_fits
+    }
+
+    // Uploads the mesh once, entities refer to it by the handle
+    pub fn register_mesh(&mut self, vertices : Vec<MeshVertex>, indices : Vec<u32>)
+        -> MeshHandle
+    {
+        self.entity_draw.meshes.register(vertices, indices)
+    }
+
+    // The stats of the last frame that collected them, once
+    pub fn take_traversal_stats(&mut self)
+        -> Option<TraversalStats>
+    {
+        self.last_traversal_stats.take()
+    }
+
+    // The gpu time of each pass in the latest frame that was read back
+    pub fn pass_timings(&self)
+        -> Option<PassTimings>
+    {
+        self.pass_timings
     }
 
-    // implement later...
-    pub fn update_render_scale(&mut self)
+    // The pass timings averaged over the recent frames
+    pub fn average_pass_timings(&self)
+        -> Option<PassTimings>
     {
+        self.average_pass_timings
+    }
+
+    // Sets the render dims from the last frame's time
+    pub fn update_render_scale<W>(&mut self, win_ctx : &mut VkWindowContext<W>)
+        where W : Send + Sync + 'static
+    {
+        if let Some(frame_time) = self.last_frame_time
+        {
+            self.render_scale.update(frame_time);
+        }
+
+        let scale = self.render_scale.scale();
+        let scaled_dims = win_ctx.scaled_dims([scale, scale]);
+        let render_dims = [scaled_dims[0].max(1), scaled_dims[1].max(1)];
 
+        let current_dims = win_ctx.dynamic_state.viewports.as_ref().unwrap()[0].dimensions;
+
+        if current_dims != [render_dims[0] as f32, render_dims[1] as f32]
+        {
+            win_ctx.update_dynamic_state_with_dims(render_dims);
+
+            // The history was rendered at the old dims
+            self.previous_camera = None;
+        }
     }
 
+    // Recreates the images at the new dims and everything that uses them
     pub fn update_dims(&mut self, dims : [u32 ; 2])
     {
         self.imgs = ImgData::new(dims, self.vk_ctx.logical_device.clone(), self.vk_ctx.queue.clone(), self.imgs.swapchain_format);
+
+        self.entity_draw.update_imgs(&self.imgs);
+        self.tree_draw.update_imgs(&self.imgs);
+        self.taa_draw.update_imgs(&self.imgs);
+        self.post_process_draw.update_imgs(&self.imgs);
+
+        // The history's pixels don't line up with the new ones
+        self.previous_camera = None;
     }
 
     pub fn cmd_buf_blit_to_swapchain<W : 'static>(&self, dynamic_state : &DynamicState, target : Arc<vulkano::image::SwapchainImage<W>>)
@@ -1,4 +1,5 @@
 use std::sync::{Arc};
+use std::collections::HashMap;
 
 use vulkano::
 {
@@ -8,13 +9,12 @@ use vulkano::
         descriptor_set::{UnsafeDescriptorSetLayout,},
     },
     framebuffer::{RenderPassAbstract, Subpass, FramebufferAbstract, Framebuffer},
-    pipeline::{GraphicsPipeline, GraphicsPipelineAbstract},
-    sync::GpuFuture,
+    pipeline::{GraphicsPipeline, GraphicsPipelineAbstract, vertex::OneVertexOneInstanceDefinition},
+    buffer::{BufferAccess, CpuBufferPool},
     command_buffer::{AutoCommandBuffer, AutoCommandBufferBuilder, DynamicState},
 };
 
 use nalgebra as na;
-use na::{UnitQuaternion, Point3, Vector3};
 
 use super::super::vk_init::VkRenderContext;
 
@@ -31,9 +31,11 @@ pub struct Pipelines
     pub raster_set_layouts : Vec<Arc<UnsafeDescriptorSetLayout>>,
 }
 
+use super::mesh_registry::{MeshRegistry, MeshVertex};
+
 #[derive(Default, Debug, Clone)]
-struct VertR { position: [f32; 3], color : [f32 ; 3] }
-vulkano::impl_vertex!(VertR, position, color);
+struct InstanceData { model : [[f32 ; 4] ; 4] }
+vulkano::impl_vertex!(InstanceData, model);
 
 mod rvs {
     vulkano_shaders::shader!{
@@ -59,7 +61,7 @@ impl Pipelines
 
         let raster =
             Arc::new(GraphicsPipeline::start()
-                .vertex_input_single_buffer::<VertR>()
+                .vertex_input(OneVertexOneInstanceDefinition::<MeshVertex, InstanceData>::new())
                 .vertex_shader(rvs.main_entry_point(), ())
                 .viewports_dynamic_scissors_irrelevant(1)
                 .depth_stencil_simple_depth()
@@ -166,6 +168,10 @@ pub struct EntityDrawer
     pub pipelines : Pipelines,
     dsets : DescriptorSetData,
     fbs : FramebufferData,
+
+    pub meshes : MeshRegistry,
+    // A new chunk of model matrices each frame
+    instance_pool : CpuBufferPool<InstanceData>,
 }
 
 impl EntityDrawer
@@ -179,82 +185,58 @@ impl EntityDrawer
         let dsets = DescriptorSetData::new(&imgs, &pipelines);
         let fbs = FramebufferData::new(&imgs, &rps);
 
-        EntityDrawer {vk_ctx, rps, pipelines, dsets, fbs}
+        let meshes = MeshRegistry::new(vk_ctx.queue.clone());
+        let instance_pool = CpuBufferPool::vertex_buffer(device.clone());
+
+        EntityDrawer {vk_ctx, rps, pipelines, dsets, fbs, meshes, instance_pool}
     }
 
+    // Binds the images again after they were recreated
+    pub fn update_imgs(&mut self, imgs : &super::ImgData)
+    {
+        self.dsets = DescriptorSetData::new(&imgs, &self.pipelines);
+        self.fbs = FramebufferData::new(&imgs, &self.rps);
+    }
 
+
+    // Draws every instance of each mesh in one call, positions are relative to the camera's chunk
     pub fn cmd_buf_raster(
         &self,
         dynamic_state : &DynamicState,
-        aspect_ratio : f32,
-        dir : UnitQuaternion<f32>,
-        pos : Point3<f32>,
-        _model_place : Vector3<f32>
+        camera : super::CameraParameters,
+        entities : &[super::EntityInstance],
+        chunk_degree : u32,
         )
         -> AutoCommandBuffer
     {
+        let dynamic_state_dims = dynamic_state.viewports.as_ref().unwrap()[0].dimensions;
+        let aspect_ratio =
+            dynamic_state_dims[0] as f32 / dynamic_state_dims[1] as f32;
 
-        let (tri_vertices, v_buf_future) = {
-            vulkano::buffer::ImmutableBuffer::from_iter(
-                vec![
-                    VertR { position: [0.0, 0.0, 0.0], color: [1.0, 1.0, 1.0]},
-                    VertR { position: [1.0, 0.0, 0.0], color: [0.0, 1.0, 0.0] },
-                    VertR { position: [1.0, 1.0, 0.0], color: [1.0, 0.0, 0.0] },
-                    VertR { position: [0.0, 1.0, 0.0], color: [0.0, 0.0, 1.0] },
-                    VertR { position: [0.0, 0.0, 1.0], color: [0.0, 1.0, 0.0] },
-                    VertR { position: [1.0, 0.0, 1.0], color: [0.0, 0.0, 1.0] },
-                    VertR { position: [1.0, 1.0, 1.0], color: [0.1, 0.1, 0.1] },
-                    VertR { position: [0.0, 1.0, 1.0], color: [0.0, 1.0, 0.0] },
-
-                ].into_iter(),
-                vulkano::buffer::BufferUsage::vertex_buffer(), self.vk_ctx.queue.clone()
-            ).unwrap()
-        };
-
-        v_buf_future.then_signal_fence_and_flush().unwrap().wait(None).unwrap();
+        // The same projection and view the rays are generated from
+        let proj = camera.settings.projection_matrix(aspect_ratio);
 
+        let view = camera.view(camera.chunk_position);
 
-
-
-
-        let (index_vertices, i_buf_future) = {
-            vulkano::buffer::ImmutableBuffer::from_iter(
-                vec![
-                    0u16, 1, 2, 2, 3, 0,
-                    2, 3, 6, 6, 3, 7,
-                    4, 5, 6, 6, 7, 4,
-                    0, 3, 4, 4, 3, 7,
-                    1, 2, 5, 5, 2, 6,
-                    0, 1, 4, 4, 1, 5
-
-                ].into_iter(),
-                vulkano::buffer::BufferUsage::index_buffer(), self.vk_ctx.queue.clone()
-            ).unwrap()
+        let pc = rvs::ty::SpaceMatrices
+        {
+            projectionView: (proj * view.to_homogeneous()).into()
         };
 
+        // Entities in other chunks are moved into the camera's chunk
+        let chunk_width = (1 << chunk_degree) as f32;
 
-        i_buf_future.then_signal_fence_and_flush().unwrap().wait(None).unwrap();
-
-
-        // Converts diagonal fov to fovy
-        let fovy = super::get_vertical_fov(150f32, aspect_ratio);
-        let proj = nalgebra::Perspective3::new(aspect_ratio, fovy, super::NEAR, super::FAR);
+        let mut instances : HashMap<_, Vec<InstanceData>> = HashMap::new();
 
-        let f_axis = dir.axis().unwrap();
-        let up = dir * f_axis.cross(&Vector3::y().cross(&f_axis));
-        
-
-        let view = nalgebra::Isometry3::look_at_rh(&pos, &(pos + f_axis.into_inner()), &-up);
-
-        let model = nalgebra::Isometry3::new(na::zero(), na::zero());
-
-        let pvm = proj.into_inner() * (view * model).to_homogeneous();
-
-
-        let pc = rvs::ty::SpaceMatrices
+        for entity in entities
         {
-            pvm: pvm.into()
-        };
+            let grid_offset = (entity.world_grid_coords - camera.world_grid_coords).map(|c| c as f32);
+            let position = entity.chunk_position.coords + grid_offset * chunk_width;
+
+            instances.entry(entity.mesh).or_default().push(
+                InstanceData {model : na::Matrix4::new_translation(&position).into()}
+            );
+        }
 
         let mut acbb = 
             AutoCommandBufferBuilder::primary_one_time_submit(
@@ -266,14 +248,23 @@ impl EntityDrawer
             self.fbs.raster.clone(), false,
             vec!([0.0, 0.0, 0.0, 0.0].into(), vulkano::format::ClearValue::Depth(1.0)),
         ).unwrap();
-        acbb
-        .draw_indexed(
-            self.pipelines.raster.clone(),
-            &dynamic_state,
-            vec![tri_vertices.clone()],
-            index_vertices,
-            (), pc
-        ).unwrap();
+        for (mesh_handle, mesh_instances) in instances
+        {
+            let mesh = self.meshes.mesh(mesh_handle);
+            let instance_buffer = Arc::new(self.instance_pool.chunk(mesh_instances).unwrap());
+
+            acbb
+            .draw_indexed(
+                self.pipelines.raster.clone(),
+                &dynamic_state,
+                vec![
+                    mesh.vertices.clone() as Arc<dyn BufferAccess + Send + Sync>,
+                    instance_buffer as Arc<dyn BufferAccess + Send + Sync>,
+                ],
+                mesh.indices.clone(),
+                (), pc
+            ).unwrap();
+        }
         acbb
         .end_render_pass().unwrap();
 
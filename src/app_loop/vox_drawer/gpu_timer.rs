@@ -0,0 +1,202 @@
+use std::sync::{Arc};
+
+use vulkano::
+{
+    VulkanObject,
+    device::{Device, DeviceOwned, Queue},
+    buffer::BufferAccess,
+    image::{ImageAccess, ImageLayout},
+    query::{UnsafeQueryPool, QueryType},
+    command_buffer::
+    {
+        CommandBuffer, CommandBufferExecError,
+        pool::standard::StandardCommandPoolAlloc,
+        sys::{UnsafeCommandBuffer, UnsafeCommandBufferBuilder, Kind, Flags},
+    },
+    sync::{GpuFuture, AccessCheckError, AccessFlagBits, PipelineStages},
+};
+
+
+// A command buffer with nothing but a timestamp write, submitted between the passes.
+// Vulkano's auto command buffers can't write timestamps.
+pub struct TimestampCommandBuffer
+{
+    inner : UnsafeCommandBuffer<StandardCommandPoolAlloc>,
+}
+
+unsafe impl DeviceOwned for TimestampCommandBuffer
+{
+    fn device(&self)
+        -> &Arc<Device>
+    {
+        self.inner.device()
+    }
+}
+
+unsafe impl CommandBuffer for TimestampCommandBuffer
+{
+    type PoolAlloc = StandardCommandPoolAlloc;
+
+    fn inner(&self)
+        -> &UnsafeCommandBuffer<StandardCommandPoolAlloc>
+    {
+        &self.inner
+    }
+
+    // Only the query pool is used, which the timer keeps alive
+    fn lock_submit(&self, _future : &dyn GpuFuture, _queue : &Queue)
+        -> Result<(), CommandBufferExecError>
+    {
+        Ok(())
+    }
+
+    unsafe fn unlock(&self)
+    {
+    }
+
+    fn check_buffer_access(&self, _buffer : &dyn BufferAccess, _exclusive : bool, _queue : &Queue)
+        -> Result<Option<(PipelineStages, AccessFlagBits)>, AccessCheckError>
+    {
+        Err(AccessCheckError::Unknown)
+    }
+
+    fn check_image_access(&self, _image : &dyn ImageAccess, _layout : ImageLayout, _exclusive : bool, _queue : &Queue)
+        -> Result<Option<(PipelineStages, AccessFlagBits)>, AccessCheckError>
+    {
+        Err(AccessCheckError::Unknown)
+    }
+}
+
+// From vk_sys, vulkano doesn't expose them
+const QUERY_RESULT_64_BIT : u32 = 0x1;
+const QUERY_RESULT_WAIT_BIT : u32 = 0x2;
+const VK_SUCCESS : u32 = 0;
+
+// A query pool for each frame in flight, so the one being read is never the one being written
+const POOL_COUNT : usize = super::FRAMES_IN_FLIGHT;
+
+// Times the gpu between timestamps written after each pass.
+// The durations between consecutive timestamps of a frame are read back once its slot comes around again.
+pub struct GpuTimer
+{
+    queue : Arc<Queue>,
+    // Empty when the queue can't write timestamps
+    pools : Vec<UnsafeQueryPool>,
+    // Whether the pool's timestamps were submitted and not read yet
+    submitted : [bool ; POOL_COUNT],
+    timestamp_count : u32,
+
+    // Nanoseconds per timestamp tick
+    timestamp_period : f32,
+    valid_mask : u64,
+}
+
+impl GpuTimer
+{
+    pub fn new(queue : Arc<Queue>, timestamp_count : u32)
+        -> GpuTimer
+    {
+        let valid_bits = queue.family().timestamp_valid_bits().unwrap_or(0);
+        let device = queue.device().clone();
+
+        // queries_range doesn't allow a range that ends at the last slot, so there is a spare
+        let pools =
+            if valid_bits == 0
+            {
+                Vec::new()
+            }
+            else
+            {
+                (0..POOL_COUNT)
+                .map(|_| UnsafeQueryPool::new(device.clone(), QueryType::Timestamp, timestamp_count + 1).unwrap())
+                .collect()
+            };
+
+        let timestamp_period = device.physical_device().limits().timestamp_period();
+        let valid_mask = if valid_bits >= 64 {!0} else {(1u64 << valid_bits) - 1};
+
+        GpuTimer {queue, pools, submitted : [false ; POOL_COUNT], timestamp_count, timestamp_period, valid_mask}
+    }
+
+    // Writes timestamp index of the frame slot's pool once everything submitted before it is done.
+    // Index 0 resets the pool first, so it has to be the frame's first timestamp.
+    // Without timestamp support the command buffer is empty.
+    pub fn cmd_timestamp(&self, slot : usize, index : u32)
+        -> TimestampCommandBuffer
+    {
+        let device = self.queue.device();
+
+        unsafe
+        {
+            let mut builder =
+                UnsafeCommandBufferBuilder::new(
+                    &Device::standard_command_pool(device, self.queue.family()),
+                    Kind::primary(),
+                    Flags::OneTimeSubmit
+                ).unwrap();
+
+            if let Some(pool) = self.pools.get(slot)
+            {
+                if index == 0
+                {
+                    builder.reset_query_pool(pool.queries_range(0, self.timestamp_count).unwrap());
+                }
+
+                builder.write_timestamp(
+                    pool.query(index).unwrap(),
+                    PipelineStages {bottom_of_pipe : true, ..PipelineStages::none()}
+                );
+            }
+
+            TimestampCommandBuffer {inner : builder.build().unwrap()}
+        }
+    }
+
+    // Call once all of the frame's timestamps were submitted
+    pub fn mark_submitted(&mut self, slot : usize)
+    {
+        self.submitted[slot] = !self.pools.is_empty();
+    }
+
+    // The milliseconds between each of the frame's timestamps and the next one.
+    // Waits for the gpu to write them, None when the pool was never submitted.
+    pub fn read(&mut self, slot : usize)
+        -> Option<Vec<f32>>
+    {
+        if !self.submitted[slot]
+        {
+            return None;
+        }
+        self.submitted[slot] = false;
+
+        let mut ticks = vec![0u64 ; self.timestamp_count as usize];
+        let device = self.queue.device();
+
+        let result = unsafe
+        {
+            device.pointers().GetQueryPoolResults(
+                device.internal_object(),
+                self.pools[slot].internal_object(),
+                0, self.timestamp_count,
+                ticks.len() * std::mem::size_of::<u64>(),
+                ticks.as_mut_ptr() as *mut _,
+                std::mem::size_of::<u64>() as u64,
+                QUERY_RESULT_64_BIT | QUERY_RESULT_WAIT_BIT
+            )
+        };
+
+        if result != VK_SUCCESS
+        {
+            return None;
+        }
+
+        Some(
+            ticks.windows(2)
+            .map(|pair|
+                ((pair[1].wrapping_sub(pair[0])) & self.valid_mask) as f32
+                * self.timestamp_period / 1_000_000.0
+            )
+            .collect()
+        )
+    }
+}
@@ -0,0 +1,107 @@
+use std::sync::{Arc};
+
+use vulkano::
+{
+    device::{Queue},
+    buffer::{ImmutableBuffer, BufferUsage},
+    sync::GpuFuture,
+};
+
+
+#[derive(Default, Debug, Clone)]
+pub struct MeshVertex { pub position: [f32; 3], pub color : [f32 ; 3] }
+vulkano::impl_vertex!(MeshVertex, position, color);
+
+// Refers to a mesh uploaded by MeshRegistry::register
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MeshHandle(usize);
+
+pub struct Mesh
+{
+    pub vertices : Arc<ImmutableBuffer<[MeshVertex]>>,
+    pub indices : Arc<ImmutableBuffer<[u32]>>,
+}
+
+// Uploads meshes once and keeps them for as long as the drawer lives
+pub struct MeshRegistry
+{
+    queue : Arc<Queue>,
+    meshes : Vec<Mesh>,
+
+    // Uploads the next frame has to wait for
+    pending_uploads : Vec<Box<dyn GpuFuture>>,
+}
+
+impl MeshRegistry
+{
+    pub fn new(queue : Arc<Queue>)
+        -> MeshRegistry
+    {
+        MeshRegistry {queue, meshes : Vec::new(), pending_uploads : Vec::new()}
+    }
+
+    // The upload isn't waited for here, the next frame is chained after it
+    pub fn register(&mut self, vertices : Vec<MeshVertex>, indices : Vec<u32>)
+        -> MeshHandle
+    {
+        let (vertices, v_buf_future) =
+            ImmutableBuffer::from_iter(
+                vertices.into_iter(), BufferUsage::vertex_buffer(), self.queue.clone()
+            ).unwrap();
+
+        let (indices, i_buf_future) =
+            ImmutableBuffer::from_iter(
+                indices.into_iter(), BufferUsage::index_buffer(), self.queue.clone()
+            ).unwrap();
+
+        self.pending_uploads.push(Box::new(v_buf_future.join(i_buf_future)));
+        self.meshes.push(Mesh {vertices, indices});
+
+        MeshHandle(self.meshes.len() - 1)
+    }
+
+    pub fn mesh(&self, handle : MeshHandle)
+        -> &Mesh
+    {
+        &self.meshes[handle.0]
+    }
+
+    // The uploads registered since the last call, joined together
+    pub fn take_uploads(&mut self)
+        -> Option<Box<dyn GpuFuture>>
+    {
+        let mut uploads = self.pending_uploads.drain(..);
+        let first = uploads.next()?;
+
+        Some(uploads.fold(first, |joined, upload| Box::new(joined.join(upload))))
+    }
+}
+
+// A unit cube with a color at each corner
+pub fn cube_mesh()
+    -> (Vec<MeshVertex>, Vec<u32>)
+{
+    let vertices =
+        vec![
+            MeshVertex { position: [0.0, 0.0, 0.0], color: [1.0, 1.0, 1.0]},
+            MeshVertex { position: [1.0, 0.0, 0.0], color: [0.0, 1.0, 0.0] },
+            MeshVertex { position: [1.0, 1.0, 0.0], color: [1.0, 0.0, 0.0] },
+            MeshVertex { position: [0.0, 1.0, 0.0], color: [0.0, 0.0, 1.0] },
+            MeshVertex { position: [0.0, 0.0, 1.0], color: [0.0, 1.0, 0.0] },
+            MeshVertex { position: [1.0, 0.0, 1.0], color: [0.0, 0.0, 1.0] },
+            MeshVertex { position: [1.0, 1.0, 1.0], color: [0.1, 0.1, 0.1] },
+            MeshVertex { position: [0.0, 1.0, 1.0], color: [0.0, 1.0, 0.0] },
+        ];
+
+    let indices =
+        vec![
+            0, 1, 2, 2, 3, 0,
+            2, 3, 6, 6, 3, 7,
+            4, 5, 6, 6, 7, 4,
+            0, 3, 4, 4, 3, 7,
+            1, 2, 5, 5, 2, 6,
+            0, 1, 4, 4, 1, 5
+        ];
+
+    (vertices, indices)
+}
@@ -75,7 +75,7 @@ impl DescriptorSetsData
         let post_set =
             Arc::new(
                 post_pool.next()
-                .add_image(imgs.world_color.clone()).unwrap()
+                .add_image(imgs.taa_resolved.clone()).unwrap()
                 .add_image(imgs.world_depth.clone()).unwrap()
                 .add_image(imgs.postprocessed.clone()).unwrap()
                 .build().unwrap()
@@ -111,6 +111,13 @@ impl PostprocessDrawer
             );
         PostprocessDrawer {vk_ctx, pipelines, dsets}
     }
+
+    // Binds the images again after they were recreated
+    pub fn update_imgs(&mut self, imgs : &super::ImgData)
+    {
+        self.dsets = DescriptorSetsData::new(&imgs, &self.pipelines);
+    }
+
     pub fn cmd_post_process(&self,
         dynamic_state : &DynamicState,
         )
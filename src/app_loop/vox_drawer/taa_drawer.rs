@@ -0,0 +1,199 @@
+use std::sync::{Arc};
+
+
+use vulkano::
+{
+    device::{Device},
+    descriptor::
+    {
+        DescriptorSet,
+        descriptor_set::{FixedSizeDescriptorSetsPool, UnsafeDescriptorSetLayout,},
+    },
+    pipeline::{ComputePipeline, ComputePipelineAbstract},
+    command_buffer::{AutoCommandBuffer, AutoCommandBufferBuilder, DynamicState},
+};
+
+use nalgebra as na;
+
+
+pub struct Pipelines
+{
+    resolve : Arc<dyn ComputePipelineAbstract + Send + Sync>,
+
+    pub resolve_set_layouts : Vec<Arc<UnsafeDescriptorSetLayout>>,
+}
+
+mod resolve_cs {
+    vulkano_shaders::shader!{
+        ty:     "compute",
+        path:   "shaders/taa_resolve.glsl"
+    }
+}
+
+// How much of each new frame goes into the history
+const CURRENT_WEIGHT : f32 = 0.1;
+
+impl Pipelines
+{
+    pub fn new(logical_device : Arc<Device>)
+        -> Pipelines
+    {
+        let device = logical_device;
+
+        let resolve =
+            Arc::new(
+                ComputePipeline::new(
+                    device.clone(),
+                    &resolve_cs::Shader::load(device.clone()).unwrap().main_entry_point(), &()
+                ).unwrap()
+            );
+
+        let resolve_set_layouts = super::get_set_layouts(&resolve.clone());
+
+        Pipelines {
+            resolve,
+            resolve_set_layouts}
+    }
+}
+
+
+struct DescriptorSetsData
+{
+    resolve_pool : FixedSizeDescriptorSetsPool,
+
+    pub resolve_set : Arc<dyn DescriptorSet + Send + Sync>,
+}
+
+impl DescriptorSetsData
+{
+    pub fn new(
+        imgs : &super::ImgData,
+        pips : &Pipelines,
+        )
+        -> DescriptorSetsData
+    {
+        let mut resolve_pool =
+            FixedSizeDescriptorSetsPool::new(pips.resolve_set_layouts[0].clone());
+
+        let resolve_set =
+            Arc::new(
+                resolve_pool.next()
+                .add_image(imgs.world_color.clone()).unwrap()
+                .add_image(imgs.ray_directions.clone()).unwrap()
+                .add_image(imgs.world_depth.clone()).unwrap()
+                .add_sampled_image(imgs.taa_history.clone(), imgs.linear_sampler.clone()).unwrap()
+                .add_image(imgs.taa_resolved.clone()).unwrap()
+                .add_image(imgs.ray_origins.clone()).unwrap()
+                .build().unwrap()
+            );
+
+
+        DescriptorSetsData {
+            resolve_pool,
+            resolve_set}
+    }
+}
+
+use super::super::vk_init::VkRenderContext;
+
+// Temporal anti-aliasing, blends the jittered frames together
+pub struct TaaDrawer
+{
+    vk_ctx : Arc<VkRenderContext>,
+    pub pipelines : Pipelines,
+    dsets : DescriptorSetsData,
+
+    taa_resolved : Arc<vulkano::image::StorageImage<vulkano::format::Format>>,
+    taa_history : Arc<vulkano::image::StorageImage<vulkano::format::Format>>,
+}
+
+impl TaaDrawer
+{
+    pub fn new(vk_ctx : Arc<VkRenderContext>, imgs : &super::ImgData)
+        -> TaaDrawer
+    {
+        let device = vk_ctx.logical_device.clone();
+        let pipelines = Pipelines::new(device.clone());
+        let dsets =
+            DescriptorSetsData::new(
+                &imgs,
+                &pipelines
+            );
+        TaaDrawer {
+            vk_ctx, pipelines, dsets,
+            taa_resolved : imgs.taa_resolved.clone(),
+            taa_history : imgs.taa_history.clone()}
+    }
+
+    // Binds the images again after they were recreated
+    pub fn update_imgs(&mut self, imgs : &super::ImgData)
+    {
+        self.dsets = DescriptorSetsData::new(&imgs, &self.pipelines);
+        self.taa_resolved = imgs.taa_resolved.clone();
+        self.taa_history = imgs.taa_history.clone();
+    }
+
+    // Resolves world_color into taa_resolved and keeps a copy as the next frame's history.
+    // Without a previous camera the current frame is used as is.
+    pub fn cmd_resolve(&self,
+        dynamic_state : &DynamicState,
+        camera : super::CameraParameters,
+        previous_camera : Option<super::CameraParameters>,
+        chunk_degree : u32,
+        )
+        -> AutoCommandBuffer
+    {
+        let dynamic_state_dims = dynamic_state.viewports.as_ref().unwrap()[0].dimensions;
+
+        let compute_dims = [(dynamic_state_dims[0] as u32 + 7) / 8, (dynamic_state_dims[1] as u32 + 7) / 8, 1];
+
+        let mut acbb =
+            AutoCommandBufferBuilder::primary_one_time_submit(
+                self.vk_ctx.logical_device.clone(), self.vk_ctx.queue.family()).unwrap();
+        acbb
+        .dispatch(
+            compute_dims,
+            self.pipelines.resolve.clone(),
+            vec!(
+                self.dsets.resolve_set.clone(),
+            ),
+            self.resolve_pc(camera, previous_camera, chunk_degree, dynamic_state_dims)
+        ).unwrap();
+
+        acbb
+        .copy_image(
+            self.taa_resolved.clone(), [0 ; 3], 0, 0,
+            self.taa_history.clone(), [0 ; 3], 0, 0,
+            [dynamic_state_dims[0] as u32, dynamic_state_dims[1] as u32, 1], 1
+        ).unwrap();
+
+        acbb.build().unwrap()
+    }
+
+    fn resolve_pc(&self,
+        camera : super::CameraParameters,
+        previous_camera : Option<super::CameraParameters>,
+        chunk_degree : u32,
+        render_dims : [f32 ; 2])
+        -> resolve_cs::ty::PushConsts
+    {
+        let previous = previous_camera.unwrap_or(camera);
+
+        // Both positions are made relative to the current chunk
+        let chunk_width = (1 << chunk_degree) as f32;
+        let grid_offset = (previous.world_grid_coords - camera.world_grid_coords).map(|c| c as f32);
+
+        let previous_position = previous.chunk_position + grid_offset * chunk_width;
+
+        // From the current camera to the previous one, then through the previous projection
+        let to_previous_camera = na::Matrix4::new_translation(&(camera.chunk_position - previous_position));
+
+        resolve_cs::ty::PushConsts
+        {
+            prevCameraToPixel : (previous.camera_to_pixel(render_dims) * to_previous_camera).into(),
+            renderDims : render_dims,
+            hasHistory : previous_camera.is_some() as u32,
+            currentWeight : CURRENT_WEIGHT,
+        }
+    }
+}
@@ -11,10 +11,11 @@ use vulkano::
     }, 
     pipeline::{ComputePipeline, ComputePipelineAbstract},
     command_buffer::{AutoCommandBuffer, AutoCommandBufferBuilder, DynamicState},
+    buffer::{BufferUsage, CpuAccessibleBuffer},
 };
 
 use nalgebra as na;
-use na::{UnitQuaternion, Point3, Vector3};
+use na::{UnitQuaternion, Vector3};
 
 pub struct Pipelines
 {
@@ -103,7 +104,8 @@ struct DescriptorSetData
     depth_to_length_pool : FixedSizeDescriptorSetsPool,
 
     pub ray_gen_set : Arc<dyn DescriptorSet + Send + Sync>,
-    pub ray_traverse_set : Arc<dyn DescriptorSet + Send + Sync>,
+    // One for each frame slot's stats buffer
+    pub ray_traverse_sets : Vec<Arc<dyn DescriptorSet + Send + Sync>>,
     pub depth_to_length_set : Arc<dyn DescriptorSet + Send + Sync>,
 }
 
@@ -112,6 +114,7 @@ impl DescriptorSetData
     pub fn new(
         imgs : &super::ImgData,
         pips : &Pipelines,
+        stats_buffers : &[Arc<CpuAccessibleBuffer<[u32 ; 5]>>],
     )
         -> DescriptorSetData
     {
@@ -124,17 +127,25 @@ impl DescriptorSetData
             Arc::new(
                 ray_gen_pool.next()
                 .add_image(imgs.ray_directions.clone()).unwrap()
+                .add_image(imgs.ray_origins.clone()).unwrap()
                 .build().unwrap()
             );
 
-        let ray_traverse_set =
-            Arc::new(
-                ray_traverse_pool.next()
-                .add_image(imgs.ray_directions.clone()).unwrap()
-                .add_image(imgs.world_color.clone()).unwrap()
-                .add_image(imgs.world_depth.clone()).unwrap()
-                .build().unwrap()
-            );
+        let ray_traverse_sets =
+            stats_buffers.iter()
+            .map(|stats_buffer|
+                Arc::new(
+                    ray_traverse_pool.next()
+                    .add_image(imgs.ray_directions.clone()).unwrap()
+                    .add_image(imgs.world_color.clone()).unwrap()
+                    .add_image(imgs.world_depth.clone()).unwrap()
+                    .add_sampled_image(imgs.raster_color.clone(), imgs.nearest_sampler.clone()).unwrap()
+                    .add_image(imgs.ray_origins.clone()).unwrap()
+                    .add_buffer(stats_buffer.clone()).unwrap()
+                    .build().unwrap()
+                ) as Arc<dyn DescriptorSet + Send + Sync>
+            )
+            .collect();
 
         let depth_to_length_set = 
             Arc::new(
@@ -147,7 +158,7 @@ impl DescriptorSetData
         DescriptorSetData
         {
             ray_gen_pool, ray_traverse_pool, depth_to_length_pool,
-            ray_gen_set, ray_traverse_set, depth_to_length_set
+            ray_gen_set, ray_traverse_sets, depth_to_length_set
         }
     }
 }
@@ -159,11 +170,17 @@ pub struct WorldDrawer
     vk_ctx : Arc<VkRenderContext>,
     pub pipelines : Pipelines,
     dsets : DescriptorSetData,
+
+    // Written by the traversal, one for each frame slot, see TraversalStats
+    stats_buffers : Vec<Arc<CpuAccessibleBuffer<[u32 ; 5]>>>,
 }
 
 
 use super::vox_map_context::VoxMapContext;
 
+// Voxels along a block's side, the traversal works in voxels
+const BLOCK_WIDTH : f32 = 32.0;
+
 impl WorldDrawer
 {
     pub fn new(vk_ctx : Arc<VkRenderContext>, imgs : &super::ImgData)
@@ -171,21 +188,57 @@ impl WorldDrawer
     {
         let device = vk_ctx.logical_device.clone();
         let pipelines = Pipelines::new(device.clone());
-        let dsets = DescriptorSetData::new(&imgs, &pipelines);
-        WorldDrawer {vk_ctx, pipelines, dsets}
+        let stats_buffers : Vec<_> =
+            (0..super::FRAMES_IN_FLIGHT)
+            .map(|_|
+                CpuAccessibleBuffer::from_data(
+                    device.clone(),
+                    BufferUsage {storage_buffer : true, ..BufferUsage::none()},
+                    false,
+                    [0u32 ; 5]
+                ).unwrap()
+            )
+            .collect();
+        let dsets = DescriptorSetData::new(&imgs, &pipelines, &stats_buffers);
+        WorldDrawer {vk_ctx, pipelines, dsets, stats_buffers}
+    }
+
+    // Binds the images again after they were recreated
+    pub fn update_imgs(&mut self, imgs : &super::ImgData)
+    {
+        self.dsets = DescriptorSetData::new(&imgs, &self.pipelines, &self.stats_buffers);
+    }
+
+    // Zeroes the slot's stats before a frame that collects them, the GPU must be done with the slot
+    pub fn reset_stats(&self, slot : usize)
+    {
+        *self.stats_buffers[slot].write().unwrap() = [0 ; 5];
+    }
+
+    // The stats of the slot's last frame, the GPU must be done with the slot
+    pub fn read_stats(&self, slot : usize)
+        -> super::TraversalStats
+    {
+        let stats = self.stats_buffers[slot].read().unwrap();
+
+        super::TraversalStats
+        {
+            total_iterations : stats[0],
+            capped_rays : stats[1],
+            max_depth : stats[2],
+            hits : stats[3],
+            misses : stats[4],
+        }
     }
 
     // Command buffer for converting the depth buffer into a buffer of ray lengths
     pub fn cmd_buf_depth_to_length(&self, 
         dynamic_state : &DynamicState,
-        dir : UnitQuaternion<f32>,
-        pos : na::Point3<f32>,
+        camera : super::CameraParameters,
     )
         -> AutoCommandBuffer
     {
         let dynamic_state_dims = dynamic_state.viewports.as_ref().unwrap()[0].dimensions;
-        let aspect_ratio =
-            dynamic_state_dims[0] as f32 / dynamic_state_dims[1] as f32;
 
         let compute_dims = [(dynamic_state_dims[0] as u32 + 7) / 8, (dynamic_state_dims[1] as u32 + 7) / 8, 1];
 
@@ -199,113 +252,143 @@ impl WorldDrawer
             compute_dims,
             self.pipelines.depth_to_length.clone(),
             self.dsets.depth_to_length_set.clone(),
-            self.depth_to_length_pc(dir, pos, aspect_ratio)
+            self.depth_to_length_pc(camera, dynamic_state_dims)
         ).unwrap();
 
         acbb.build().unwrap()
     }
 
-    pub fn cmd_buf_draw(
+    // Command buffer for generating the camera rays, separate from the traversal to time them apart
+    pub fn cmd_buf_ray_gen(
         &self,
         dynamic_state : &DynamicState,
-        vox_map_ctx : &VoxMapContext,
         camera : super::CameraParameters,
         frame_index : i32)
         -> AutoCommandBuffer
     {
-
         let dynamic_state_dims = dynamic_state.viewports.as_ref().unwrap()[0].dimensions;
         let compute_dims = [(dynamic_state_dims[0] as u32 + 7) / 8, (dynamic_state_dims[1] as u32 + 7) / 8, 1];
-        let aspect_ratio =
-            dynamic_state_dims[0] as f32 / dynamic_state_dims[1] as f32;
-
 
         let mut acbb = 
             AutoCommandBufferBuilder::primary_one_time_submit(
                 self.vk_ctx.logical_device.clone(), self.vk_ctx.queue.family()).unwrap();
 
-        // Voxel ray trace
         acbb
         .dispatch(
             compute_dims,
             self.pipelines.ray_gen.clone(),
             self.dsets.ray_gen_set.clone(),
-            self.ray_gen_pc(camera.orientation, aspect_ratio, frame_index)
+            self.ray_gen_pc(camera, dynamic_state_dims, frame_index)
         ).unwrap();
+
+        acbb.build().unwrap()
+    }
+
+    // Command buffer for tracing the rays from cmd_buf_ray_gen through the voxels
+    pub fn cmd_buf_draw(
+        &self,
+        dynamic_state : &DynamicState,
+        vox_map_ctx : &VoxMapContext,
+        camera : super::CameraParameters,
+        lighting : super::LightingSettings,
+        debug_view : super::DebugView,
+        collect_stats : bool,
+        slot : usize,
+        frame_index : i32)
+        -> AutoCommandBuffer
+    {
+
+        let dynamic_state_dims = dynamic_state.viewports.as_ref().unwrap()[0].dimensions;
+        let compute_dims = [(dynamic_state_dims[0] as u32 + 7) / 8, (dynamic_state_dims[1] as u32 + 7) / 8, 1];
+
+
+        let mut acbb = 
+            AutoCommandBufferBuilder::primary_one_time_submit(
+                self.vk_ctx.logical_device.clone(), self.vk_ctx.queue.family()).unwrap();
+
+        // Voxel ray trace
         acbb
         .dispatch(
             compute_dims,
             self.pipelines.ray_traverse.clone(),
             vec!(
                 vox_map_ctx.tree_set.clone(),
-                self.dsets.ray_traverse_set.clone(),
+                self.dsets.ray_traverse_sets[slot].clone(),
                 vox_map_ctx.prefab_set.clone(), 
             ), 
-            self.ray_traverse_pc(camera, vox_map_ctx.chunk_count())
+            self.ray_traverse_pc(camera, lighting, debug_view, collect_stats, vox_map_ctx, dynamic_state_dims, frame_index)
         ).unwrap();
         
         acbb.build().unwrap()
     }
 
 
-    fn ray_gen_pc(&self, dir : UnitQuaternion<f32>, aspect_ratio : f32, frame_index : i32)
-        -> ray_gen_cs::ty::Orient
+    fn ray_gen_pc(&self, camera : super::CameraParameters, render_dims : [f32 ; 2], frame_index : i32)
+        -> ray_gen_cs::ty::PushConsts
     {
-        let cam_axes = get_camera_orientation(dir);
-
-        let fov = 150.0f32;
-
-        let f_basis =
-            (cam_axes.2
-            * (aspect_ratio).hypot(1.0))
-            / (fov.to_radians() / 2.0).tan();
-        
-        ray_gen_cs::ty::Orient
+        ray_gen_cs::ty::PushConsts
         {
-            hDir : (cam_axes.0).into(),
-            vDir : (cam_axes.1).into(),
-            fBasis : f_basis.into(),
+            pixelToCamera : camera.pixel_to_camera(render_dims).into(),
             frameIndex : frame_index,
-            _dummy0 : Default:: default(),
-            _dummy1 : Default:: default(),
         }
     }
 
-    fn depth_to_length_pc(&self, dir : UnitQuaternion<f32>,
-        pos : Point3<f32>, aspect_ratio : f32)
+    fn depth_to_length_pc(&self, camera : super::CameraParameters, render_dims : [f32 ; 2])
         -> depth_to_length_cs::ty::PushConsts
     {
-        let cam_axes = get_camera_orientation(dir);
-
-        let fov = 150.0f32;
-
-
-        let target = pos + cam_axes.2;
-
-        // println!("{}", cam_axes.1);
-        let pc = depth_to_length_cs::ty::PushConsts
+        depth_to_length_cs::ty::PushConsts
         {
-            pos: pos.coords.into(),
-            // gridPos : pos.coords.into(),
-            projInv : nalgebra::Perspective3::new(aspect_ratio, super::get_vertical_fov(fov, aspect_ratio), super::NEAR, super::FAR).inverse().into(),
-            viewInv : nalgebra::Isometry3::look_at_rh(&pos, &target, &-cam_axes.1).inverse().to_homogeneous().into(),
-            _dummy0 : Default:: default(),
-        };
-
-        pc
+            pixelToCamera : camera.pixel_to_camera(render_dims).into(),
+        }
     }
 
 
 
-    fn ray_traverse_pc(&self, camera : super::CameraParameters, chunk_count : u32)
+    fn ray_traverse_pc(&self, 
+        camera : super::CameraParameters, 
+        lighting : super::LightingSettings,
+        debug_view : super::DebugView,
+        collect_stats : bool,
+        vox_map_ctx : &VoxMapContext, 
+        render_dims : [f32 ; 2],
+        frame_index : i32)
         -> ray_traverse_cs::ty::PushConsts
     {
-        // let thing = ray_traverse_cs::ty::RayResult {};
-        // println!("{}", cam_axes.1);
+        let aspect_ratio = render_dims[0] / render_dims[1];
+
+        // Perspective pixels get wider with distance, orthographic ones are as wide everywhere
+        let (pixel_angle, pixel_size) = match camera.settings.projection
+        {
+            super::Projection::Perspective =>
+            (
+                // Rays are spread over the screen's height by the tangent of half the vertical fov
+                2.0 * (super::get_vertical_fov(camera.settings.fov, aspect_ratio) / 2.0).tan() / render_dims[1],
+                0.0
+            ),
+            super::Projection::Orthographic {height} =>
+            (
+                0.0,
+                height * BLOCK_WIDTH / render_dims[1]
+            ),
+        };
+
         let pc = ray_traverse_cs::ty::PushConsts
         {
             pos: camera.chunk_position.coords.into(),
-            chunkCount: chunk_count,
+            chunkCount: vox_map_ctx.chunk_count(),
+            prefabCount: vox_map_ctx.prefab_count(),
+            pixelAngle: pixel_angle,
+            gridDegree: vox_map_ctx.grid_degree(),
+            chunkDegree: vox_map_ctx.chunk_degree(),
+            sunDir: lighting.sun_direction.normalize().into(),
+            sunIntensity: lighting.sun_intensity,
+            skyColor: lighting.sky_color.into(),
+            ambientIntensity: lighting.ambient_intensity,
+            frameIndex: frame_index,
+            aoRadius: lighting.ao_radius,
+            pixelSize: pixel_size,
+            debugView: debug_view as u32,
+            collectStats: collect_stats as u32,
         };
         pc
     }
@@ -7,7 +7,7 @@ use vulkano::
         DescriptorSet,    
         descriptor_set::{FixedSizeDescriptorSetsPool, PersistentDescriptorSet},
     },
-    image::{StorageImage, Dimensions, ImageUsage, ImmutableImage},
+    image::{StorageImage, Dimensions, ImageUsage},
     format::{Format},
     sampler,
     buffer::{CpuBufferPool},
@@ -17,10 +17,12 @@ use vulkano::
 
 use super::super::super::world_engine as world_eng;
 
-use world_eng::map::Map;
+use world_eng::map::{Map, TREE_IMAGE_WIDTH};
 
 use world_eng::data_structures::SESVOctree;
 
+use world_eng::object::VoxelPrefab;
+
 
 
 // use super::top_down_world_drawer::ray_traverse_cs as TreeGLSL;
@@ -29,8 +31,10 @@ use world_eng::data_structures::SESVOctree;
 pub struct VoxMapContext
 {
     
-    palette_volume_atlas : Arc<ImmutableImage<Format>>,
-    palette_array : Arc<ImmutableImage<Format>>,
+    palette_volume_atlas : Arc<StorageImage<Format>>,
+    normal_volume_atlas : Arc<StorageImage<Format>>,
+    palette_array : Arc<StorageImage<Format>>,
+    material_array : Arc<StorageImage<Format>>,
 
     sampler : Arc<sampler::Sampler>,
 
@@ -41,8 +45,15 @@ pub struct VoxMapContext
     pub prefab_set : Arc<dyn DescriptorSet + Send + Sync>,
 
     tree_update_buffer : CpuBufferPool<u32>,
+    palette_volume_update_buffer : CpuBufferPool<u8>,
+    normal_volume_update_buffer : CpuBufferPool<u32>,
+    palette_update_buffer : CpuBufferPool<u32>,
+    material_update_buffer : CpuBufferPool<[f32 ; 4]>,
 
     chunk_count : u32,
+    prefab_count : u32,
+    grid_degree : u32,
+    chunk_degree : u32,
 }
 
 
@@ -61,7 +72,9 @@ impl VoxMapContext
 
         let mut tree_pool = FixedSizeDescriptorSetsPool::new(pips_tree.ray_traverse_set_layouts[0].clone());
 
-        let tree_img = allocate_vk_tree_img(queue.clone(), chunk_count, 1);
+        let prefab_count = map.prefab_manager.prefabs.len() as u32;
+
+        let tree_img = allocate_vk_tree_img(queue.clone(), chunk_count, prefab_count);
 
 
         let sampler = 
@@ -77,28 +90,40 @@ impl VoxMapContext
             ).unwrap();
 
         
-        let (palette_volume_atlas, pva_future) = 
-        ImmutableImage::from_iter(
-            map.prefab_manager.prefabs[0].palette_volume().iter().cloned(),
-            Dimensions::Dim3d {width: 32, height: 32, depth: 32},
-            Format::R8Uint,
-            queue.clone()
-        ).unwrap();
-        
-        
+        // Prefab images are storage images so a single prefab's slot can be rewritten
+        let prefab_img = |dimensions, format|
+            StorageImage::with_usage(
+                queue.device().clone(),
+                dimensions,
+                format,
+                ImageUsage {transfer_destination : true, sampled : true, ..ImageUsage::none()},
+                vec!(queue.family()),
+            ).unwrap();
 
-        let (palette_array, pa_future) = 
-        ImmutableImage::from_iter(
-            map.prefab_manager.prefabs[0].palette().iter().cloned(),
-            Dimensions::Dim1dArray {width: 256, array_layers: 1},
-            Format::R8G8B8A8Unorm,
-            queue.clone()
-        ).unwrap();
+        // Every prefab's palette volume is stacked along the z axis of the atlas,
+        // so a prefab's slot starts at a depth of 32 * prefab index
+        let palette_volume_atlas = 
+            prefab_img(Dimensions::Dim3d {width: 32, height: 32, depth: 32 * prefab_count}, Format::R8Uint);
+
+        // Laid out like the palette volume atlas, a packed normal per voxel
+        let normal_volume_atlas = 
+            prefab_img(Dimensions::Dim3d {width: 32, height: 32, depth: 32 * prefab_count}, Format::R8G8B8A8Snorm);
+
+        // One palette per array layer, indexed by prefab
+        let palette_array = 
+            prefab_img(Dimensions::Dim1dArray {width: 256, array_layers: prefab_count}, Format::R8G8B8A8Unorm);
+
+        // The first 256 texels of a layer hold the first texel of every material,
+        // the next 256 hold the second (see VoxelMaterial::to_texels)
+        let material_array = 
+            prefab_img(Dimensions::Dim1dArray {width: 512, array_layers: prefab_count}, Format::R32G32B32A32Sfloat);
         
         let prefab_set =
             Arc::new(PersistentDescriptorSet::start(pips_tree.ray_traverse_set_layouts[2].clone())
             .add_sampled_image(palette_volume_atlas.clone(), sampler.clone()).unwrap()
             .add_sampled_image(palette_array.clone(), sampler.clone()).unwrap()
+            .add_sampled_image(material_array.clone(), sampler.clone()).unwrap()
+            .add_sampled_image(normal_volume_atlas.clone(), sampler.clone()).unwrap()
             .build().unwrap());
 
         let tree_set =
@@ -110,8 +135,13 @@ impl VoxMapContext
             );
 
         let vmc = 
-            VoxMapContext { palette_volume_atlas, palette_array, sampler, tree_img, tree_pool, tree_set, prefab_set,
-                tree_update_buffer, chunk_count};
+            VoxMapContext { palette_volume_atlas, normal_volume_atlas, palette_array, material_array, sampler, tree_img, tree_pool, tree_set, prefab_set,
+                tree_update_buffer, 
+                palette_volume_update_buffer : CpuBufferPool::upload(queue.device().clone()),
+                normal_volume_update_buffer : CpuBufferPool::upload(queue.device().clone()),
+                palette_update_buffer : CpuBufferPool::upload(queue.device().clone()),
+                material_update_buffer : CpuBufferPool::upload(queue.device().clone()),
+                chunk_count, prefab_count, grid_degree : map.grid_degree(), chunk_degree : map.chunk_exponent()};
 
         let mut init_acbb =
             AutoCommandBufferBuilder::primary_one_time_submit(queue.device().clone(), queue.family()).unwrap();
@@ -121,8 +151,6 @@ impl VoxMapContext
 
         vulkano::sync::now(queue.device().clone())
         .then_execute(queue.clone(), init_acbb.build().unwrap()).unwrap()
-        .join(pa_future)
-        .join(pva_future)
         .then_signal_fence()
         .wait(None).unwrap();
     
@@ -131,7 +159,95 @@ impl VoxMapContext
 
     pub fn insert_prefabs(&self, acbb : &mut AutoCommandBufferBuilder, map : &Map)
     {
-        self.update_image_tree(acbb, &map.prefab_manager.prefabs[0].tree_volume(), (4 + map.chunk_count()) as u32);
+        for (prefab_index, prefab) in map.prefab_manager.prefabs.iter().enumerate()
+        {
+            self.upload_prefab(acbb, prefab_index as u32, prefab.as_ref());
+        }
+    }
+
+    // Writes a prefab into its slot of every prefab image.
+    // Each prefab tree is given its own layer after the chunk layers,
+    // so a block id maps directly to the layer of its prefab
+    pub fn upload_prefab(&self, acbb : &mut AutoCommandBufferBuilder, prefab_index : u32, prefab : &dyn VoxelPrefab)
+    {
+        if prefab_index >= self.prefab_count
+        {
+            panic!("Prefab was registered after the prefab images were allocated!");
+        }
+
+        self.update_image_tree(acbb, &prefab.tree_volume(), self.prefab_layer(prefab_index));
+
+        let palette_volume_data = 
+            Arc::new(self.palette_volume_update_buffer.chunk(prefab.palette_volume()).unwrap());
+
+        acbb
+        .copy_buffer_to_image_dimensions(
+            palette_volume_data,
+            self.palette_volume_atlas.clone(),
+            [0, 0, 32 * prefab_index],
+            [32, 32, 32],
+            0,
+            1,
+            0
+        ).unwrap();
+
+        let normal_volume_data = 
+            Arc::new(self.normal_volume_update_buffer.chunk(prefab.normal_volume()).unwrap());
+
+        acbb
+        .copy_buffer_to_image_dimensions(
+            normal_volume_data,
+            self.normal_volume_atlas.clone(),
+            [0, 0, 32 * prefab_index],
+            [32, 32, 32],
+            0,
+            1,
+            0
+        ).unwrap();
+
+        let palette_data = 
+            Arc::new(self.palette_update_buffer.chunk(prefab.palette().to_vec()).unwrap());
+
+        acbb
+        .copy_buffer_to_image_dimensions(
+            palette_data,
+            self.palette_array.clone(),
+            [0 ; 3],
+            [256, 1, 1],
+            prefab_index,
+            1,
+            0
+        ).unwrap();
+
+        let materials = prefab.materials();
+        let material_texels : Vec<[f32 ; 4]> = 
+            (0..512).map(|i| materials[i % 256].to_texels()[i / 256]).collect();
+
+        let material_data = 
+            Arc::new(self.material_update_buffer.chunk(material_texels).unwrap());
+
+        acbb
+        .copy_buffer_to_image_dimensions(
+            material_data,
+            self.material_array.clone(),
+            [0 ; 3],
+            [512, 1, 1],
+            prefab_index,
+            1,
+            0
+        ).unwrap();
+    }
+
+    pub fn chunk_layer(&self, chunk_index : u32)
+        -> u32
+    {
+        TOP_TREE_NUM + chunk_index
+    }
+
+    pub fn prefab_layer(&self, prefab_index : u32)
+        -> u32
+    {
+        TOP_TREE_NUM + self.chunk_count + prefab_index
     }
     
     
@@ -142,16 +258,56 @@ impl VoxMapContext
         let mut builder =
             AutoCommandBufferBuilder::primary_one_time_submit(queue.device().clone(), queue.family()).unwrap();
 
-        // update render tree for chunks
-        if let Some(tree) = map.render_tree()
+        // prefabs whose source files were edited are written over their old slots
+        for prefab_index in map.prefab_manager.reload_changed()
         {
-            self.update_image_tree(&mut builder, tree, 4);
+            self.upload_prefab(&mut builder, prefab_index as u32, map.prefab_manager.prefabs[prefab_index].as_ref());
         }
 
+        // A new view radius changes the number of chunk layers, every tree is written again
+        if map.chunk_count() as u32 != self.chunk_count
+        {
+            self.resize_tree_img(queue.clone(), &mut builder, map);
+        }
+
+        // chunks that changed are written to their own layers
+        for c_index in map.take_dirty_chunks()
+        {
+            if let Some(tree) = map.chunk_tree(c_index)
+            {
+                self.update_image_tree(&mut builder, tree, self.chunk_layer(c_index as u32));
+            }
+        }
+
+        // the grid tree is small and holds each chunk's lod level, so it's rebuilt every frame
+        self.grid_degree = map.grid_degree();
+        self.chunk_degree = map.chunk_exponent();
+        self.update_image_tree(&mut builder, &map.world_grid_tree(), GRID_TREE_LAYER);
+
         builder
         .build().unwrap()
     }
 
+    fn resize_tree_img(&mut self, queue : Arc<Queue>, acbb : &mut AutoCommandBufferBuilder, map : &Map)
+    {
+        self.chunk_count = map.chunk_count() as u32;
+
+        self.tree_img = allocate_vk_tree_img(queue, self.chunk_count, self.prefab_count);
+
+        self.tree_set =
+            Arc::new(
+                self.tree_pool.next()
+                .add_image(self.tree_img.clone()).unwrap()
+                .build()
+                .unwrap()
+            );
+
+        for (prefab_index, prefab) in map.prefab_manager.prefabs.iter().enumerate()
+        {
+            self.update_image_tree(acbb, &prefab.tree_volume(), self.prefab_layer(prefab_index as u32));
+        }
+    }
+
     fn update_image_tree(&self, acbb : &mut AutoCommandBufferBuilder, tree : &SESVOctree, layer : u32)
     {
         let nodes : Vec<u32> = tree.cds().iter().map(|n| n.to_u32()).collect();
@@ -177,17 +333,36 @@ impl VoxMapContext
         self.chunk_count
     }
 
+    pub fn prefab_count(&self)
+        -> u32
+    {
+        self.prefab_count
+    }
+
+    pub fn grid_degree(&self)
+        -> u32
+    {
+        self.grid_degree
+    }
+
+    pub fn chunk_degree(&self)
+        -> u32
+    {
+        self.chunk_degree
+    }
+
 }
 
+const TOP_TREE_NUM : u32 = 4; // world grid trees
+const GRID_TREE_LAYER : u32 = 0;
+
 fn allocate_vk_tree_img(queue : Arc<Queue>, chunk_count : u32, volume_prefab_count : u32)
     -> Arc<StorageImage<Format>>
 {
-    const TOP_TREE_NUM : u32 = 4; // world grid trees
-
     StorageImage::with_usage(
         queue.device().clone(),
         Dimensions::Dim1dArray {
-            width : 4700, 
+            width : TREE_IMAGE_WIDTH as u32, 
             array_layers : TOP_TREE_NUM + chunk_count + volume_prefab_count},
         Format::R32Uint,
         ImageUsage {transfer_destination : true, storage : true, ..ImageUsage::none()},
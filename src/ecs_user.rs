@@ -1,10 +1,14 @@
 use nalgebra as na;
 use na::{Point3, Vector3};
 
+use super::app_loop::vox_drawer::MeshHandle;
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct ChunkPositionComponent(pub Point3<f32>);
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct WorldGridCoordinateComponent(pub Point3<i32>);
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct VelocityComponent(pub Vector3<f32>);
-
+// Drawn by the entity drawer at the entity's position
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MeshComponent(pub MeshHandle);
@@ -122,6 +122,12 @@ impl InputData
         input_data.add_keys("right", set!(33, 77));
         input_data.add_keys("high", set!(57));
         input_data.add_keys("low", set!(42));
+        input_data.add_keys("view_radius_up", set!(27));
+        input_data.add_keys("view_radius_down", set!(26));
+        input_data.add_keys("chunk_size_up", set!(13));
+        input_data.add_keys("chunk_size_down", set!(12));
+        input_data.add_keys("debug_view", set!(61));
+        input_data.add_keys("place_structure", set!(25));
 
         input_data
     }
@@ -15,11 +15,26 @@ use nalgebra as na;
 
 fn main()
 {
+    // Renders an icon of every prefab and exits, without opening a window
+    if std::env::args().any(|arg| arg == "--render-icons")
+    {
+        world_engine::voxel_manager::PrefabManager::new().render_icons("resources/icons", 64).unwrap();
+        return;
+    }
+
+    // --debug-view <name> starts with that view instead of the lit one
+    let debug_view =
+        std::env::args().skip_while(|arg| arg != "--debug-view").nth(1)
+        .map(|name| 
+            app_loop::vox_drawer::DebugView::from_name(&name)
+            .unwrap_or_else(|| panic!("Unknown debug view {}", name)))
+        .unwrap_or(app_loop::vox_drawer::DebugView::Lit);
+
     let cam_dir = 
         Arc::new(RwLock::new(nalgebra::UnitQuaternion::from_axis_angle(
                     &na::Unit::new_unchecked(na::Vector3::z()), 0.0001)
         ));
     let input_data = Arc::new(input::InputState::new());
 
-    app_loop::run(input_data.clone(), cam_dir.clone());
+    app_loop::run(input_data.clone(), cam_dir.clone(), debug_view);
 }
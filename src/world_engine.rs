@@ -10,6 +10,8 @@ pub mod map;
 
 pub mod displaced_chunks;
 
+pub mod block_state;
+
 
 pub trait ChunkGenerator
 {
@@ -0,0 +1,186 @@
+use nalgebra as na;
+
+// Axis permutations, the first three are even (no mirroring on their own).
+// Must match PERMUTATIONS in tree_traverse.glsl
+const PERMUTATIONS : [[usize ; 3] ; 6] =
+[
+    [0, 1, 2],
+    [1, 2, 0],
+    [2, 0, 1],
+    [0, 2, 1],
+    [2, 1, 0],
+    [1, 0, 2],
+];
+
+// One of the 48 axis aligned orientations of a block.
+// Block axis i takes prefab axis permutation[i], then is flipped if flip bit i is set.
+// 24 of these are rotations, the other 24 are mirrored rotations.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BlockOrientation
+{
+    permutation : u8,
+    flips : u8,
+}
+
+impl BlockOrientation
+{
+    pub const COUNT : u8 = 48;
+
+    pub fn identity()
+        -> BlockOrientation
+    {
+        BlockOrientation {permutation : 0, flips : 0}
+    }
+
+    pub fn from_index(index : u8)
+        -> BlockOrientation
+    {
+        if index >= BlockOrientation::COUNT
+        {
+            panic!("Invalid block orientation index!");
+        }
+
+        BlockOrientation {permutation : index >> 3, flips : index & 7}
+    }
+
+    pub fn index(&self)
+        -> u8
+    {
+        (self.permutation << 3) | self.flips
+    }
+
+    pub fn all()
+        -> impl Iterator<Item = BlockOrientation>
+    {
+        (0..BlockOrientation::COUNT).map(BlockOrientation::from_index)
+    }
+
+    // The 24 orientations that don't mirror
+    pub fn rotations()
+        -> impl Iterator<Item = BlockOrientation>
+    {
+        BlockOrientation::all().filter(|o| !o.is_mirrored())
+    }
+
+    pub fn is_mirrored(&self)
+        -> bool
+    {
+        let odd_permutation = self.permutation >= 3;
+        let odd_flips = self.flips.count_ones() % 2 == 1;
+
+        odd_permutation != odd_flips
+    }
+
+    // Maps prefab space directions to block space directions
+    pub fn matrix(&self)
+        -> na::Matrix3<i32>
+    {
+        let axes = PERMUTATIONS[self.permutation as usize];
+
+        let mut matrix = na::Matrix3::zeros();
+
+        for (block_axis, &prefab_axis) in axes.iter().enumerate()
+        {
+            matrix[(block_axis, prefab_axis)] = if (self.flips >> block_axis) & 1 == 1 {-1} else {1};
+        }
+
+        matrix
+    }
+
+    // Returns none if the matrix isn't a signed permutation matrix
+    pub fn from_matrix(matrix : na::Matrix3<i32>)
+        -> Option<BlockOrientation>
+    {
+        BlockOrientation::all().find(|o| o.matrix() == matrix)
+    }
+
+    // This orientation followed by the other
+    pub fn then(&self, other : BlockOrientation)
+        -> BlockOrientation
+    {
+        BlockOrientation::from_matrix(other.matrix() * self.matrix()).unwrap()
+    }
+
+    // A quarter turn counter clockwise about an axis (0 => x, 1 => y, 2 => z)
+    pub fn quarter_turn(axis : usize)
+        -> BlockOrientation
+    {
+        let (next, last) = ((axis + 1) % 3, (axis + 2) % 3);
+
+        let mut matrix = na::Matrix3::zeros();
+        matrix[(axis, axis)] = 1;
+        matrix[(last, next)] = 1;
+        matrix[(next, last)] = -1;
+
+        BlockOrientation::from_matrix(matrix).unwrap()
+    }
+
+    // A mirror along an axis
+    pub fn mirror(axis : usize)
+        -> BlockOrientation
+    {
+        BlockOrientation {permutation : 0, flips : 1 << axis}
+    }
+
+    // The block space coords of a prefab voxel, in a cube of the width
+    pub fn to_block_coords(&self, prefab_coords : [usize ; 3], width : usize)
+        -> [usize ; 3]
+    {
+        let axes = PERMUTATIONS[self.permutation as usize];
+
+        let mut block_coords = [0 ; 3];
+
+        for block_axis in 0..3
+        {
+            let c = prefab_coords[axes[block_axis]];
+
+            block_coords[block_axis] = if (self.flips >> block_axis) & 1 == 1 {width - 1 - c} else {c};
+        }
+
+        block_coords
+    }
+}
+
+// A block id along with its orientation.
+// Stored in chunk leaves as id | orientation index << 8
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BlockState
+{
+    pub block : u8,
+    pub orientation : BlockOrientation,
+}
+
+impl BlockState
+{
+    pub fn new(block : u8)
+        -> BlockState
+    {
+        BlockState {block, orientation : BlockOrientation::identity()}
+    }
+
+    pub fn to_value(&self)
+        -> u32
+    {
+        self.block as u32 | ((self.orientation.index() as u32) << 8)
+    }
+
+    pub fn from_value(value : u32)
+        -> BlockState
+    {
+        let orientation_index = ((value >> 8) & 0xFF) as u8;
+
+        BlockState
+        {
+            block : (value & 0xFF) as u8,
+            orientation :
+                if orientation_index < BlockOrientation::COUNT
+                {
+                    BlockOrientation::from_index(orientation_index)
+                }
+                else
+                {
+                    BlockOrientation::identity()
+                },
+        }
+    }
+}
@@ -85,6 +85,7 @@ impl super::ChunkGenerator for TerrainChunkGenerator
         let sphere_world_grid_pos = na::Point3::new(1, 2, -1);
 
         let chunk_dims_signed = [chunk_dims[0] as i32, chunk_dims[1] as i32, chunk_dims[2] as i32];
+        let chunk_dims_vec : na::Vector3<i32> = chunk_dims_signed.into();
 
         for x in 0..(chunk_dims_signed[0])
         {
@@ -121,7 +122,7 @@ impl super::ChunkGenerator for TerrainChunkGenerator
                 let world_grid_point : na::Point3<i32> = world_grid_position.into();
 
                 let sphere_disp = 
-                    ((sphere_world_grid_pos - world_grid_point) * 16)
+                    (sphere_world_grid_pos - world_grid_point).component_mul(&chunk_dims_vec)
                     + (sphere_chunk_grid_pos - na::Point3::new(x, y, z));
 
                 let radius = 15;
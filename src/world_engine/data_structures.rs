@@ -68,6 +68,7 @@ type IntPos = na::Point3<i32>;
 // Simple Efficient Sparse Voxel Octree:
 // It's like an esvo, but there's no contours,
 // and the insertion is so simple that it wastes nodes
+#[derive(Clone)]
 pub struct SESVOctree
 {
     cds : Vec<ChildDescriptor>,
@@ -129,8 +130,11 @@ impl SESVOctree
 
             let depth_is_final = depth == (self.degree - 1);
 
+            // an existing leaf has its value replaced
             if target_is_valid && depth_is_final
             {
+                let child_index = (self.cds[cd_index].octuple_index << 3) as usize | target_octant as usize;
+                self.cds[child_index].octuple_index = value as u16;
                 break;
             }
            
@@ -201,6 +205,100 @@ impl SESVOctree
         }
     }
 
+    // The value of the leaf at pos.
+    // Returns none if there's no leaf or pos is outside the tree.
+    pub fn get(&self, pos : IntPos)
+        -> Option<u32>
+    {
+        let width = 1 << self.degree;
+        let pos_relative = pos - self.pos;
+
+        if pos_relative.iter().any(|&c| c < 0 || c >= width)
+        {
+            return None;
+        }
+
+        let mut cd_index = 0;
+        let mut node_pos = self.pos;
+
+        for depth in 0..self.degree
+        {
+            let target_octant = self.select_octant(pos, node_pos, depth);
+
+            if !self.cds[cd_index].is_child_valid(target_octant)
+            {
+                return None;
+            }
+
+            node_pos = self.next_node_pos(node_pos, target_octant as i32, depth);
+
+            cd_index = ((self.cds[cd_index].octuple_index as usize) << 3) | target_octant as usize;
+        }
+
+        self.cds.get(cd_index).map(|cd| cd.octuple_index as u32)
+    }
+
+    // Gives every interior node the most common leaf value beneath it,
+    // so a traversal can stop early and still have a value to show.
+    // leaf_value is given a leaf's position and the value stored in it (see insert),
+    // if the leaf has a descriptor.
+    pub fn build_lod<F>(&mut self, leaf_value : F)
+        where F : Fn(IntPos, Option<u32>) -> u8
+    {
+        let root_pos = self.pos;
+
+        self.lod_node(0, root_pos, 0, &leaf_value);
+    }
+
+    // Returns how often each value appears in the leaves beneath the node
+    fn lod_node<F>(&mut self, cd_index : usize, node_pos : IntPos, depth : u32, leaf_value : &F)
+        -> Vec<(u8, u32)>
+        where F : Fn(IntPos, Option<u32>) -> u8
+    {
+        let mut counts : Vec<(u8, u32)> = Vec::new();
+
+        for octant in 0..8
+        {
+            if !self.cds[cd_index].is_child_valid(octant)
+            {
+                continue;
+            }
+
+            let child_pos = self.next_node_pos(node_pos, octant as i32, depth);
+            let child_index = ((self.cds[cd_index].octuple_index as usize) << 3) | octant as usize;
+
+            // leaves may not have descriptors (see insert_no_val), only valid bits
+            let child_counts = 
+                if depth == self.degree - 1
+                {
+                    let stored_value = self.cds.get(child_index).map(|cd| cd.octuple_index as u32);
+
+                    vec!((leaf_value(child_pos, stored_value), 1))
+                }
+                else
+                {
+                    self.lod_node(child_index, child_pos, depth + 1, leaf_value)
+                };
+
+            for (value, count) in child_counts
+            {
+                match counts.iter_mut().find(|(v, _)| *v == value)
+                {
+                    Some((_, total)) => *total += count,
+                    None => counts.push((value, count)),
+                }
+            }
+        }
+
+        // ties go to the lowest value, so the result doesn't depend on child order
+        if let Some(&(value, _)) = counts.iter().max_by_key(|&&(v, count)| (count, std::cmp::Reverse(v)))
+        {
+            self.cds[cd_index].lod_value = value;
+        }
+
+        counts
+    }
+
     fn select_octant(&self, pos : IntPos, node_pos : IntPos, node_depth : u32)
         -> u32
     {
@@ -232,6 +330,7 @@ pub struct ChildDescriptor
 {
     pub octuple_index : u16,
     pub valid_mask : u8, // nth bit => nth child is valid
+    pub lod_value : u8, // see SESVOctree::build_lod
 }
 
 
@@ -240,7 +339,7 @@ impl ChildDescriptor
     pub fn new_null()
         -> ChildDescriptor
     {
-        ChildDescriptor {octuple_index : std::u16::MAX, valid_mask : 0}
+        ChildDescriptor {octuple_index : std::u16::MAX, valid_mask : 0, lod_value : 0}
     }
 
     pub fn is_child_valid(&self, octant : u32)
@@ -264,6 +363,6 @@ impl ChildDescriptor
     pub fn to_u32(&self)
         -> u32
     {
-        ((self.valid_mask as u32) << 16) | (self.octuple_index as u32)
+        ((self.lod_value as u32) << 24) | ((self.valid_mask as u32) << 16) | (self.octuple_index as u32)
     }
 }
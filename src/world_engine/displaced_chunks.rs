@@ -1,9 +1,58 @@
 use std::collections::HashSet;
 
 use super::data_structures::SESVOctree;
+use super::block_state::BlockState;
 use nalgebra as na;
 
-pub const CHUNK_EXPONENT: u32 = 4;
+// Chunk lod levels, by distance from the viewer (in chunks):
+// 0 => every voxel of every block
+// 1 => a voxel per block
+// n => a voxel per 2^(n - 1) blocks along each axis, up to a voxel for the whole chunk
+// (level chunk exponent + 1)
+#[derive(Clone, Copy, Debug)]
+pub struct LodRings
+{
+    // Chunks closer than this are drawn at full detail
+    pub full_detail_radius : f32,
+    // How far each further lod level reaches
+    pub ring_width : f32,
+    // A chunk only changes level once it's this far past a ring's edge,
+    // so a viewer on the edge doesn't make it swap back and forth
+    pub hysteresis : f32,
+}
+
+impl LodRings
+{
+    pub fn level(&self, distance : f32, max_level : u8)
+        -> u8
+    {
+        if distance <= self.full_detail_radius
+        {
+            return 0;
+        }
+
+        let ring = ((distance - self.full_detail_radius) / self.ring_width) as u8;
+
+        (ring + 1).min(max_level)
+    }
+
+    // The level after moving, the current level is kept while within the hysteresis
+    pub fn next_level(&self, current_level : u8, distance : f32, max_level : u8)
+        -> u8
+    {
+        let nearest = self.level(distance - self.hysteresis, max_level);
+        let furthest = self.level(distance + self.hysteresis, max_level);
+
+        if (nearest..=furthest).contains(&current_level)
+        {
+            current_level
+        }
+        else
+        {
+            self.level(distance, max_level)
+        }
+    }
+}
 
 // Displaced Chunks are chunks of map data that are used
 // based on their integer displacement from the chunk the viewer resides in.
@@ -25,6 +74,12 @@ pub struct DisplacedChunks
     
     // If true, then the chunk needs to be written to the gpu
     dirty_flags : Vec<bool>,
+
+    // See LodRings
+    lod_levels : Vec<u8>,
+
+    // Chunks are 2^chunk_exponent blocks wide
+    chunk_exponent : u32,
     
     // The set of all possible chunk displacements
     displacement_set : HashSet<na::Vector3<i32>>,
@@ -33,14 +88,14 @@ pub struct DisplacedChunks
 
 impl DisplacedChunks
 {
-    pub fn new(displacement_set : HashSet<na::Vector3<i32>>)
+    pub fn new(displacement_set : HashSet<na::Vector3<i32>>, chunk_exponent : u32)
         -> DisplacedChunks
     {
         let num = displacement_set.len();
 
         let blocks = 
             (0..num).into_iter()
-            .map(|_| SESVOctree::new(na::Point3::origin(), CHUNK_EXPONENT))
+            .map(|_| SESVOctree::new(na::Point3::origin(), chunk_exponent))
             .collect();
 
         DisplacedChunks 
@@ -49,6 +104,8 @@ impl DisplacedChunks
             displacement : displacement_set.iter().cloned().collect(),
             in_use_flags : vec![false ; num],
             dirty_flags : vec![false ; num],
+            lod_levels : vec![0 ; num],
+            chunk_exponent,
             displacement_set,
         }
     }
@@ -59,14 +116,59 @@ impl DisplacedChunks
         self.blocks.len()
     }
 
-    pub fn insert_block(&mut self, chunk_index : usize, block : u8, pos_in_chunk : na::Point3<i32>)
+    // Changes the set of displacements. In use chunks still within the new set are kept,
+    // the rest of the set starts out unused and is generated again.
+    // Chunk indices change, so the kept chunks are flagged dirty.
+    pub fn resize(&mut self, displacement_set : HashSet<na::Vector3<i32>>)
+    {
+        let mut blocks = Vec::new();
+        let mut displacement = Vec::new();
+        let mut lod_levels = Vec::new();
+
+        for (index, tree) in std::mem::take(&mut self.blocks).into_iter().enumerate()
+        {
+            if self.in_use_flags[index] && displacement_set.contains(&self.displacement[index])
+            {
+                blocks.push(tree);
+                displacement.push(self.displacement[index]);
+                lod_levels.push(self.lod_levels[index]);
+            }
+        }
+
+        let kept_count = blocks.len();
+        let kept_set : HashSet<na::Vector3<i32>> = displacement.iter().cloned().collect();
+
+        for &new_displacement in displacement_set.difference(&kept_set)
+        {
+            blocks.push(SESVOctree::new(na::Point3::origin(), self.chunk_exponent));
+            displacement.push(new_displacement);
+            lod_levels.push(0);
+        }
+
+        let num = displacement_set.len();
+
+        self.blocks = blocks;
+        self.displacement = displacement;
+        self.lod_levels = lod_levels;
+        self.in_use_flags = (0..num).map(|index| index < kept_count).collect();
+        self.dirty_flags = self.in_use_flags.clone();
+        self.displacement_set = displacement_set;
+    }
+
+    pub fn chunk_exponent(&self)
+        -> u32
+    {
+        self.chunk_exponent
+    }
+
+    pub fn insert_block(&mut self, chunk_index : usize, block : BlockState, pos_in_chunk : na::Point3<i32>)
     {
         if !self.in_use_flags[chunk_index]
         {
             panic!("Attempted to insert into chunk not in use!");
         }
 
-        self.blocks[chunk_index].insert(pos_in_chunk, block as u32);
+        self.blocks[chunk_index].insert(pos_in_chunk, block.to_value());
         self.dirty_flags[chunk_index] = true;
     }
 
@@ -148,12 +250,62 @@ impl DisplacedChunks
         some_index
     }
 
+    // The in use chunks that changed since the last call, their flags are cleared.
+    // Their trees are given lod values (the most common block id beneath each node).
+    pub fn take_dirty_chunks(&mut self)
+        -> Vec<usize>
+    {
+        let dirty : Vec<usize> = 
+            (0..self.len())
+            .filter(|&index| self.in_use_flags[index] && self.dirty_flags[index])
+            .collect();
+
+        for &index in &dirty
+        {
+            self.blocks[index].build_lod(|_, value| value.map_or(0, |v| BlockState::from_value(v).block));
+            self.dirty_flags[index] = false;
+        }
+
+        dirty
+    }
+
+    // Sets the lod level of each chunk from its center's distance to the viewer.
+    // viewer_pos is the viewer's position in its chunk, in chunks (0 to 1)
+    pub fn update_lod_levels(&mut self, rings : &LodRings, viewer_pos : na::Vector3<f32>)
+    {
+        let max_level = self.chunk_exponent as u8 + 1;
+
+        for index in 0..self.len()
+        {
+            let center = self.displacement[index].map(|c| c as f32 + 0.5);
+
+            let distance = (center - viewer_pos).norm();
+
+            self.lod_levels[index] = rings.next_level(self.lod_levels[index], distance, max_level);
+        }
+    }
+
+    pub fn lod_level(&self, index : usize)
+        -> u8
+    {
+        self.lod_levels[index]
+    }
+
     pub fn get_displacement(&self, index : usize)
         -> na::Vector3<i32>
     {
         self.displacement[index]
     }
 
+    // The index of the in use chunk with the displacement
+    pub fn chunk_index(&self, displacement : na::Vector3<i32>)
+        -> Option<usize>
+    {
+        (0..self.len())
+        .find(|&index| 
+            self.in_use_flags[index] && self.displacement[index] == displacement)
+    }
+
     pub fn is_chunk_in_use(&self, index : usize)
         -> bool
     {
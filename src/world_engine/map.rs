@@ -10,13 +10,114 @@ use world_eng::chunk_generators::{TerrainChunkGenerator};
 
 use std::collections::HashSet;
 
-use world_eng::displaced_chunks::DisplacedChunks;
+use world_eng::displaced_chunks::{DisplacedChunks, LodRings};
+
+use world_eng::object::vox_writer::{VoxWriter, MAX_MODEL_WIDTH};
+use world_eng::object::standard_voxel_prefab::PREFAB_WIDTH;
+
+use world_eng::block_state::BlockState;
 
 use super::super::input as input;
 
 use input::KeyEventQueue;
 
 
+// The prefab tree is 2^5 voxels wide, must match PREFAB_DEGREE in tree_traverse.glsl
+const PREFAB_DEGREE : u32 = 5;
+
+// Must match MAX_TREE_DEGREE in tree_traverse.glsl
+const MAX_TREE_DEGREE : u32 = 16;
+
+// Each chunk has a layer of the tree image, which shares a common limit of 2048 layers
+// with the top trees and up to 256 prefabs.
+// (grid tree leaves have 12 bits for the chunk index, see Map::world_grid_tree)
+const MAX_CHUNK_COUNT : usize = 2048 - 4 - 256;
+
+// Texels in a row of the tree image, each tree layer is one row.
+// A full chunk tree has to fit in a row, which caps the chunk exponent at 4.
+pub const TREE_IMAGE_WIDTH : usize = 4700;
+
+// The nodes stored for a completely full tree of the degree, every level down to the leaves.
+// The root has an octuple of its own, so 7 of its slots are unused.
+fn worst_case_tree_nodes(degree : u32)
+    -> usize
+{
+    (0..=degree)
+    .map(|level| 1usize.checked_shl(3 * level).unwrap_or(std::usize::MAX))
+    .fold(7, usize::saturating_add)
+}
+
+// How the chunks around the viewer are laid out
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MapSettings
+{
+    // Chunks within this many chunks of the viewer are kept
+    pub view_radius : usize,
+    // Chunks are 2^chunk_exponent blocks wide
+    pub chunk_exponent : u32,
+}
+
+impl MapSettings
+{
+    pub fn default()
+        -> MapSettings
+    {
+        MapSettings {view_radius : 2, chunk_exponent : 4}
+    }
+
+    // A ray has to fit the grid tree, a chunk tree and a prefab tree in its stack
+    pub fn validate(&self)
+        -> Result<(), String>
+    {
+        if self.view_radius == 0 || self.chunk_exponent == 0
+        {
+            return Err(format!("{:?}: view radius and chunk exponent must be positive", self));
+        }
+
+        let chunk_nodes = worst_case_tree_nodes(self.chunk_exponent);
+
+        if chunk_nodes > TREE_IMAGE_WIDTH
+        {
+            return Err(format!("{:?}: a full chunk has {} nodes, more than the tree image's {}", self, chunk_nodes, TREE_IMAGE_WIDTH));
+        }
+
+        let chunk_count = Map::radius_displacement_set(self.view_radius).len();
+
+        if chunk_count > MAX_CHUNK_COUNT
+        {
+            return Err(format!("{:?}: {} chunks is more than {}", self, chunk_count, MAX_CHUNK_COUNT));
+        }
+
+        let tree_degree = Map::grid_degree_for(self.view_radius) + self.chunk_exponent + PREFAB_DEGREE;
+
+        if tree_degree > MAX_TREE_DEGREE
+        {
+            return Err(format!("{:?}: a tree degree of {} is more than {}", self, tree_degree, MAX_TREE_DEGREE));
+        }
+
+        Ok(())
+    }
+}
+
+// Converts a position (chunk coords and the position in the chunk, in blocks) to another chunk size
+pub fn rescale_chunk_position(
+    world_grid_pos : na::Point3<i32>, 
+    chunk_pos : na::Point3<f32>, 
+    old_chunk_exponent : u32, 
+    new_chunk_exponent : u32)
+    -> (na::Point3<i32>, na::Point3<f32>)
+{
+    let old_width = (1 << old_chunk_exponent) as f32;
+    let new_width = (1 << new_chunk_exponent) as f32;
+
+    let block_pos = world_grid_pos.coords.map(|c| c as f32 * old_width) + chunk_pos.coords;
+
+    (
+        na::Point3::from(block_pos.map(|c| (c / new_width).floor() as i32)),
+        na::Point3::from(block_pos.map(|c| c.rem_euclid(new_width)))
+    )
+}
+
 // A map organizes and manages a set of chunks
 pub struct Map
 {
@@ -26,6 +127,10 @@ pub struct Map
 
     world_grid_pos : na::Point3<i32>,
 
+    settings : MapSettings,
+
+    pub lod_rings : LodRings,
+
     pub prefab_manager : PrefabManager,
 
     input_event_queue : KeyEventQueue,
@@ -33,18 +138,24 @@ pub struct Map
 
 impl Map
 {
-    pub fn new(viewer_world_grid_pos : na::Point3<i32>, view_radius : usize)
+    pub fn new(viewer_world_grid_pos : na::Point3<i32>, settings : MapSettings)
         -> Map
     {
+        settings.validate().unwrap();
+
         let prefab_manager = PrefabManager::new();
 
         Map
         {
-            chunks : DisplacedChunks::new(Map::radius_displacement_set(view_radius)),
+            chunks : DisplacedChunks::new(Map::radius_displacement_set(settings.view_radius), settings.chunk_exponent),
             chunk_generator : Box::new(TerrainChunkGenerator::new()),
             world_grid_pos : viewer_world_grid_pos,
+            settings,
+            lod_rings : LodRings {full_detail_radius : 2.0, ring_width : 1.5, hysteresis : 0.25},
             prefab_manager,
-            input_event_queue : KeyEventQueue::new(set!("interact_1", "interact_2"))
+            input_event_queue : 
+                KeyEventQueue::new(set!("interact_1", "interact_2", "place_structure", 
+                    "view_radius_up", "view_radius_down", "chunk_size_up", "chunk_size_down"))
         }
     }
 
@@ -90,10 +201,48 @@ impl Map
         &mut self.input_event_queue
     }
 
+    pub fn settings(&self)
+        -> MapSettings
+    {
+        self.settings
+    }
+
+    // A new chunk size drops every chunk, they're all generated again
+    // and the viewer's chunk coords are converted.
+    // A new view radius only generates the chunks that weren't in view before.
+    pub fn set_settings(&mut self, settings : MapSettings)
+        -> Result<(), String>
+    {
+        settings.validate()?;
+
+        self.world_grid_pos = 
+            rescale_chunk_position(self.world_grid_pos, na::Point3::origin(), 
+                self.settings.chunk_exponent, settings.chunk_exponent).0;
+
+        if settings.chunk_exponent != self.settings.chunk_exponent
+        {
+            self.chunks = DisplacedChunks::new(Map::radius_displacement_set(settings.view_radius), settings.chunk_exponent);
+        }
+        else if settings.view_radius != self.settings.view_radius
+        {
+            self.chunks.resize(Map::radius_displacement_set(settings.view_radius));
+        }
+
+        self.settings = settings;
+
+        Ok(())
+    }
+
+    pub fn chunk_exponent(&self)
+        -> u32
+    {
+        self.settings.chunk_exponent
+    }
+
     pub fn chunk_dims(&self)
         -> [usize ; 3]
     {
-        [1 << world_eng::displaced_chunks::CHUNK_EXPONENT ; 3]
+        [1 << self.chunk_exponent() ; 3]
     }
 
     pub fn chunk_len(&self)
@@ -110,25 +259,208 @@ impl Map
         self.chunks.len()
     }
 
-    pub fn handle_events(&mut self, _look_dir : na::Vector3<f32>, _world_grid_coord : na::Point3<i32>, _chunk_pos : na::Point3<f32>)
+    pub fn handle_events(&mut self, _look_dir : na::Vector3<f32>, world_grid_coord : na::Point3<i32>, chunk_pos : na::Point3<f32>)
     {
+        let mut settings = self.settings;
+
+        let c_width = 1 << self.chunk_exponent();
+        let viewer_block_pos = world_grid_coord * c_width + chunk_pos.coords.map(|c| c.floor() as i32);
+
+        while let Some((keys, pressed)) = self.input_event_queue.pop_event()
+        {
+            if !pressed
+            {
+                continue;
+            }
+
+            let key = |name : &str| keys.contains(name);
+
+            if key("view_radius_up") {settings.view_radius += 1;}
+            if key("view_radius_down") {settings.view_radius = settings.view_radius.saturating_sub(1);}
+            if key("chunk_size_up") {settings.chunk_exponent += 1;}
+            if key("chunk_size_down") {settings.chunk_exponent = settings.chunk_exponent.saturating_sub(1);}
+
+            if key("place_structure")
+            {
+                let placed = self.place_structure(0, viewer_block_pos);
+                println!("Placed {} structure blocks at {:?}", placed, viewer_block_pos);
+            }
+        }
 
+        if settings != self.settings
+        {
+            match self.set_settings(settings)
+            {
+                Ok(()) => println!("Map settings changed: {:?}", settings),
+                Err(err) => println!("Map settings unchanged: {}", err),
+            }
+        }
     }
 
 
-    // Temporary method
-    pub fn render_tree(&self)
-        -> Option<&SESVOctree>
+    // The degree of the world grid tree, wide enough for every displacement in the view radius
+    pub fn grid_degree(&self)
+        -> u32
+    {
+        Map::grid_degree_for(self.settings.view_radius)
+    }
+
+    fn grid_degree_for(view_radius : usize)
+        -> u32
+    {
+        let mut degree = 1;
+
+        while (1 << (degree - 1)) < view_radius
+        {
+            degree += 1;
+        }
+
+        degree
+    }
+
+    // A tree over the chunks around the viewer, the viewer's chunk is at 2^(degree - 1) along each axis.
+    // Each in use chunk is a leaf storing its chunk index | lod level << 12
+    pub fn world_grid_tree(&self)
+        -> SESVOctree
     {
-        for c_index in 0..self.chunk_len() 
+        let degree = self.grid_degree();
+        let half_width = 1 << (degree - 1);
+
+        let mut tree = SESVOctree::new(na::Point3::origin(), degree);
+
+        for c_index in 0..self.chunk_count()
         {
-            if self.chunks.get_displacement(c_index) == na::Vector3::repeat(0)
+            if !self.chunks.is_chunk_in_use(c_index)
             {
-                return self.chunks.get_tree(c_index);
+                continue;
             }
+
+            let grid_pos = na::Point3::from(self.chunks.get_displacement(c_index).add_scalar(half_width));
+
+            tree.insert(grid_pos, c_index as u32 | ((self.chunks.lod_level(c_index) as u32) << 12));
+        }
+
+        tree
+    }
+
+    // The chunks to write to the gpu, see DisplacedChunks::take_dirty_chunks
+    pub fn take_dirty_chunks(&mut self)
+        -> Vec<usize>
+    {
+        self.chunks.take_dirty_chunks()
+    }
+
+    pub fn chunk_tree(&self, c_index : usize)
+        -> Option<&SESVOctree>
+    {
+        self.chunks.get_tree(c_index)
+    }
+
+    // chunk_pos is the viewer's position in its chunk, in blocks
+    pub fn update_lod_levels(&mut self, chunk_pos : na::Point3<f32>)
+    {
+        let c_width = (1 << self.chunk_exponent()) as f32;
+
+        self.chunks.update_lod_levels(&self.lod_rings, chunk_pos.coords / c_width);
+    }
+
+    // Splits a world block position into its chunk's index and its position in the chunk
+    fn locate_block(&self, world_block_pos : na::Point3<i32>)
+        -> Option<(usize, na::Point3<i32>)>
+    {
+        let c_width = 1 << self.chunk_exponent();
+
+        let chunk_world_grid_pos = na::Point3::from(world_block_pos.coords.map(|c| c.div_euclid(c_width)));
+        let pos_in_chunk = na::Point3::from(world_block_pos.coords.map(|c| c.rem_euclid(c_width)));
+
+        let c_index = self.chunks.chunk_index(chunk_world_grid_pos - self.world_grid_pos)?;
+
+        Some((c_index, pos_in_chunk))
+    }
+
+    // The block at a world block position,
+    // none if the block is empty or its chunk isn't loaded
+    pub fn get_block(&self, world_block_pos : na::Point3<i32>)
+        -> Option<BlockState>
+    {
+        let (c_index, pos_in_chunk) = self.locate_block(world_block_pos)?;
+
+        self.chunks.get_tree(c_index)?
+            .get(pos_in_chunk)
+            .map(BlockState::from_value)
+    }
+
+    // Places (or replaces) a block along with its orientation.
+    // Returns false if the block's chunk isn't loaded.
+    pub fn place_block(&mut self, world_block_pos : na::Point3<i32>, block : BlockState)
+        -> bool
+    {
+        match self.locate_block(world_block_pos)
+        {
+            Some((c_index, pos_in_chunk)) =>
+            {
+                self.chunks.insert_block(c_index, block, pos_in_chunk);
+                true
+            },
+            None => false
         }
+    }
+
+    // Places the blocks of a registered structure with its minimum corner at a world block position.
+    // Blocks in chunks that aren't loaded are skipped, returns the number placed.
+    pub fn place_structure(&mut self, structure_index : usize, min_block_pos : na::Point3<i32>)
+        -> usize
+    {
+        let blocks = match self.prefab_manager.structures.get(structure_index)
+        {
+            Some(structure) => structure.blocks.clone(),
+            None => return 0,
+        };
+
+        blocks.into_iter()
+        .filter(|(offset, block)| self.place_block(min_block_pos + offset, BlockState::new(*block)))
+        .count()
+    }
+
+    // Saves the blocks from min to max (inclusive) as a .vox file,
+    // each block is expanded into the voxels of its prefab.
+    // The region is a single model, so it can be at most MAX_MODEL_WIDTH / PREFAB_WIDTH blocks wide.
+    pub fn save_region(&self, file : &str, min : na::Point3<i32>, max : na::Point3<i32>)
+        -> std::io::Result<()>
+    {
+        let block_extent = (max - min).map(|c| (c + 1).max(0) as usize);
+        let max_blocks = MAX_MODEL_WIDTH / PREFAB_WIDTH;
+
+        if block_extent.iter().any(|&e| e == 0 || e > max_blocks)
+        {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput,
+                format!("Region of {:?} blocks can't be saved, each side must be 1 to {} blocks", 
+                    block_extent.as_slice(), max_blocks)));
+        }
+
+        let mut writer = VoxWriter::new(
+            [block_extent.x * PREFAB_WIDTH, block_extent.y * PREFAB_WIDTH, block_extent.z * PREFAB_WIDTH]);
+
+        for x in 0..block_extent.x {
+        for y in 0..block_extent.y {
+        for z in 0..block_extent.z {
+
+            let block_offset = na::Vector3::new(x as i32, y as i32, z as i32);
+
+            let block = self.get_block(min + block_offset);
+
+            let prefab = block.and_then(|block| self.prefab_manager.prefabs.get(block.block as usize));
+
+            if let (Some(block), Some(prefab)) = (block, prefab)
+            {
+                writer.insert_prefab(
+                    [x * PREFAB_WIDTH, y * PREFAB_WIDTH, z * PREFAB_WIDTH], 
+                    prefab.as_ref(), block.orientation);
+            }
+
+        }}}
 
-        None
+        writer.save(file)
     }
 
     pub fn adapt_to_world_position(&mut self, viewer_world_grid_pos : na::Point3<i32>)
@@ -156,7 +488,7 @@ impl Map
 
             self.chunk_generator.generate_chunk(&mut block_buffer, chunk_world_grid_pos.coords.into(), self.chunk_dims());
 
-            let c_width = 1 << world_eng::displaced_chunks::CHUNK_EXPONENT;
+            let c_width = 1 << self.chunk_exponent();
             let c_area = c_width * c_width;
 
             for index in 0..self.chunk_len() 
@@ -170,7 +502,7 @@ impl Map
                 
                 let coords = [index_cast % c_width, (index_cast % c_area) / c_width, index_cast / c_area];
 
-                self.chunks.insert_block(c_index, block_buffer[index], coords.into());
+                self.chunks.insert_block(c_index, BlockState::new(block_buffer[index]), coords.into());
                 
             }
         }
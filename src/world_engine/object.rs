@@ -1,4 +1,6 @@
 use super::super::world_engine::data_structures::SESVOctree;
+use byte_voxels::BitVoxels;
+use dot_vox_wrapper::DotVoxWrapper;
 
 pub trait VoxelPrefab
 {
@@ -6,12 +8,144 @@ pub trait VoxelPrefab
         -> SESVOctree;
     fn palette_volume(&self)
         -> Vec<u8>;
+    // see voxel_normals::normal_volume
+    fn normal_volume(&self)
+        -> Vec<u32>;
     fn palette(&self)
         -> [u32 ; 256];
+    fn materials(&self)
+        -> [VoxelMaterial ; 256];
+    fn bit_voxels(&self)
+        -> &BitVoxels;
+}
+
+// A voxel model drawn as flat textures, one per face
+pub trait TexturedVoxelPrefab
+{
+    fn dims(&self)
+        -> [usize ; 3];
+    // Every texture, one after another
+    fn get_texture(&self)
+        -> Vec<u32>;
+    fn texture_count(&self)
+        -> u32;
+    // The texture used by each face (top, front, right, left, back, bottom)
+    fn face_texture_offsets(&self)
+        -> [u32 ; 6];
+}
+
+// The rendering properties of a palette entry, from a .vox file's MATL chunks
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VoxelMaterial
+{
+    pub emission : f32,
+    pub transparency : f32,
+    pub roughness : f32,
+    pub metal : f32,
+    pub ior : f32,
+}
+
+impl VoxelMaterial
+{
+    // A plain diffuse material
+    pub fn diffuse()
+        -> VoxelMaterial
+    {
+        VoxelMaterial {emission : 0.0, transparency : 0.0, roughness : 1.0, metal : 0.0, ior : 1.0}
+    }
+
+    // Packed as two texels for the material palette texture
+    pub fn to_texels(&self)
+        -> [[f32 ; 4] ; 2]
+    {
+        [
+            [self.emission, self.transparency, self.roughness, self.metal],
+            [self.ior, 0.0, 0.0, 0.0]
+        ]
+    }
+}
+
+// The plane a model is projected onto, named by the texture's (u, v) axes.
+// The remaining axis is the depth the projection looks along.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VoxFace
+{
+    XY,
+    XZ,
+    ZY,
+}
+
+impl VoxFace
+{
+    // (u, v, depth) axes
+    pub fn axes(&self)
+        -> [usize ; 3]
+    {
+        match self
+        {
+            VoxFace::XY => [0, 1, 2],
+            VoxFace::XZ => [0, 2, 1],
+            VoxFace::ZY => [2, 1, 0],
+        }
+    }
+
+    pub fn texture_dims(&self, dims : [usize ; 3])
+        -> [usize ; 2]
+    {
+        let axes = self.axes();
+
+        [dims[axes[0]], dims[axes[1]]]
+    }
+}
+
+// Projects a model onto a face, each texel is the color of the first voxel along the depth axis.
+// flips => [u, v, depth], flipping the depth looks from the far side.
+// Texels with no voxel behind them are transparent (0).
+pub fn get_ortho_texture(
+    volume : &BitVoxels, 
+    vox_data : &DotVoxWrapper, 
+    model_index : usize, 
+    face : VoxFace, 
+    flips : [bool ; 3])
+    -> Vec<u32>
+{
+    let dims = volume.dims();
+    let axes = face.axes();
+    let [width, height] = face.texture_dims(dims);
+    let depth = dims[axes[2]];
+
+    let flip = |c : usize, len : usize, flipped : bool| if flipped {len - 1 - c} else {c};
+
+    let mut texture = vec![0 ; width * height];
+
+    for v in 0..height {
+    for u in 0..width {
+
+        for d in 0..depth
+        {
+            let mut coords = [0 ; 3];
+            coords[axes[0]] = flip(u, width, flips[0]);
+            coords[axes[1]] = flip(v, height, flips[1]);
+            coords[axes[2]] = flip(d, depth, flips[2]);
+
+            if volume.get_voxel(coords)
+            {
+                texture[u + v * width] = vox_data.get_voxel_color(coords, model_index).unwrap_or(0);
+                break;
+            }
+        }
+
+    }}
+
+    texture
 }
 
 
 mod dot_vox_wrapper;
-mod byte_voxels;
-// pub mod voxel_barrel;
+mod vox_scene;
+pub mod byte_voxels;
+pub mod vox_writer;
+pub mod voxel_normals;
+pub mod icon_renderer;
+pub mod voxel_barrel;
 pub mod standard_voxel_prefab;
\ No newline at end of file
@@ -10,6 +10,10 @@ use std::ops::{BitAnd, BitAndAssign, BitOrAssign};
 // use dot_vox as dv;
 use super::dot_vox_wrapper::DotVoxWrapper;
 
+// Bits of a byte whose voxel has a coordinate of 0 along the axis (x, y, z).
+// The rest of the bits have a coordinate of 1.
+const LOW_BITS : [u8 ; 3] = [0b0101_0101, 0b0011_0011, 0b0000_1111];
+
 
 // Data dims represents the extent to which a coordinate can be converted to an index for the data vec
 // data_dims = (5, 6, 3) => max_coords = (4, 5, 2)
@@ -19,11 +23,8 @@ impl BitVoxels
         -> BitVoxels
     {
         let dims = vox_data.dims(model_index);
-        let data_dims = [(dims[0] + 1) / 2, (dims[1] + 1) / 2, (dims[2] + 1) / 2];
-        
-        let data = vec![0 ; data_dims[0] * data_dims[1] * data_dims[2]];
 
-        let mut b_voxels = BitVoxels {dims, data};
+        let mut b_voxels = BitVoxels::empty(dims);
 
 
         for vox in vox_data.voxel_slice(model_index)
@@ -34,6 +35,16 @@ impl BitVoxels
         b_voxels
     }
 
+    pub fn empty(dims : [usize ; 3])
+        -> BitVoxels
+    {
+        let data_dims = [(dims[0] + 1) / 2, (dims[1] + 1) / 2, (dims[2] + 1) / 2];
+        
+        let data = vec![0 ; data_dims[0] * data_dims[1] * data_dims[2]];
+
+        BitVoxels {dims, data}
+    }
+
     // Changes an individual bit from the u8
     // the bit represents whether a voxel is present or not
     pub fn set_voxel(&mut self, coords : [usize ; 3], existence : bool)
@@ -86,4 +97,307 @@ impl BitVoxels
         BitVoxels::bit_pos_from_bool_coords(
             [(coords[0] % 2) != 0, (coords[1] % 2) != 0, (coords[2] % 2) != 0])
     }
+
+
+    fn data_dims(&self)
+        -> [usize ; 3]
+    {
+        [(self.dims[0] + 1) / 2, (self.dims[1] + 1) / 2, (self.dims[2] + 1) / 2]
+    }
+
+    fn assert_same_dims(&self, other : &BitVoxels)
+    {
+        if self.dims != other.dims
+        {
+            panic!("BitVoxels must have the same dims! {:?} != {:?}", self.dims, other.dims);
+        }
+    }
+
+    // Combines each byte with the other's byte at the same index
+    fn zip_bytes(&self, other : &BitVoxels, op : impl Fn(u8, u8) -> u8)
+        -> BitVoxels
+    {
+        self.assert_same_dims(other);
+
+        let data = self.data.iter().zip(other.data.iter()).map(|(&a, &b)| op(a, b)).collect();
+
+        BitVoxels {dims : self.dims, data}
+    }
+
+    pub fn union(&self, other : &BitVoxels)
+        -> BitVoxels
+    {
+        self.zip_bytes(other, |a, b| a | b)
+    }
+
+    pub fn intersection(&self, other : &BitVoxels)
+        -> BitVoxels
+    {
+        self.zip_bytes(other, |a, b| a & b)
+    }
+
+    // Voxels of self that aren't in other
+    pub fn difference(&self, other : &BitVoxels)
+        -> BitVoxels
+    {
+        self.zip_bytes(other, |a, b| a & !b)
+    }
+
+    // The number of present voxels
+    pub fn popcount(&self)
+        -> usize
+    {
+        self.data.iter().map(|byte| byte.count_ones() as usize).sum()
+    }
+
+    // Moves every voxel by the offset, voxels moved out of the dims are lost
+    pub fn translate(&self, offset : [i32 ; 3])
+        -> BitVoxels
+    {
+        // Even offsets move whole bytes, what's left over is a single voxel step.
+        // Rounding toward zero keeps voxels the step would bring back from being lost.
+        let byte_offset = [offset[0] / 2, offset[1] / 2, offset[2] / 2];
+
+        let mut translated = self.shift_bytes(byte_offset);
+
+        for axis in 0..3
+        {
+            if offset[axis] % 2 != 0
+            {
+                translated = translated.step(axis, offset[axis] > 0);
+            }
+        }
+
+        translated.clear_padding();
+
+        translated
+    }
+
+    // Grows the shape by a voxel in each of the 6 directions, per step
+    pub fn dilate(&self, steps : usize)
+        -> BitVoxels
+    {
+        let mut dilated = self.clone();
+
+        for _ in 0..steps
+        {
+            let mut grown = dilated.clone();
+
+            for axis in 0..3
+            {
+                for &positive in &[true, false]
+                {
+                    grown = grown.union(&dilated.step(axis, positive));
+                }
+            }
+
+            grown.clear_padding();
+            dilated = grown;
+        }
+
+        dilated
+    }
+
+    // Shrinks the shape by a voxel in each of the 6 directions, per step.
+    // Voxels on the edge of the dims are treated as having empty neighbors.
+    pub fn erode(&self, steps : usize)
+        -> BitVoxels
+    {
+        let mut eroded = self.clone();
+
+        for _ in 0..steps
+        {
+            let mut shrunk = eroded.clone();
+
+            for axis in 0..3
+            {
+                for &positive in &[true, false]
+                {
+                    shrunk = shrunk.intersection(&eroded.step(axis, positive));
+                }
+            }
+
+            eroded = shrunk;
+        }
+
+        eroded
+    }
+
+    // Voxels with at least one empty face neighbor.
+    // Voxels on the edge of the dims are always on the surface.
+    pub fn surface(&self)
+        -> BitVoxels
+    {
+        self.difference(&self.erode(1))
+    }
+
+    // The minimum and maximum (inclusive) coords of present voxels,
+    // none if there are no voxels
+    pub fn bounding_box(&self)
+        -> Option<([usize ; 3], [usize ; 3])>
+    {
+        let data_dims = self.data_dims();
+
+        let mut bounds : Option<([usize ; 3], [usize ; 3])> = None;
+
+        for (index, &byte) in self.data.iter().enumerate()
+        {
+            if byte == 0
+            {
+                continue;
+            }
+
+            let byte_coords = 
+            [
+                index % data_dims[0], 
+                (index / data_dims[0]) % data_dims[1], 
+                index / (data_dims[0] * data_dims[1])
+            ];
+
+            let mut byte_min = [0 ; 3];
+            let mut byte_max = [0 ; 3];
+
+            for axis in 0..3
+            {
+                byte_min[axis] = byte_coords[axis] * 2 + (byte & LOW_BITS[axis] == 0) as usize;
+                byte_max[axis] = byte_coords[axis] * 2 + (byte & !LOW_BITS[axis] != 0) as usize;
+            }
+
+            bounds = Some(match bounds
+            {
+                Some((min, max)) => 
+                (
+                    [min[0].min(byte_min[0]), min[1].min(byte_min[1]), min[2].min(byte_min[2])],
+                    [max[0].max(byte_max[0]), max[1].max(byte_max[1]), max[2].max(byte_max[2])]
+                ),
+                None => (byte_min, byte_max),
+            });
+        }
+
+        bounds
+    }
+
+    // Crops the dims to the bounding box.
+    // Returns the cropped voxels and the offset of the crop in the original coords.
+    pub fn shrink_to_bounds(&self)
+        -> (BitVoxels, [usize ; 3])
+    {
+        let (min, max) = match self.bounding_box()
+        {
+            Some(bounds) => bounds,
+            None => return (BitVoxels::empty([0 ; 3]), [0 ; 3]),
+        };
+
+        // once the minimum corner is at the origin, the cropped bytes are a corner of the old bytes
+        let moved = self.translate([-(min[0] as i32), -(min[1] as i32), -(min[2] as i32)]);
+
+        let mut shrunk = BitVoxels::empty([max[0] - min[0] + 1, max[1] - min[1] + 1, max[2] - min[2] + 1]);
+
+        let old_data_dims = moved.data_dims();
+        let new_data_dims = shrunk.data_dims();
+
+        for bz in 0..new_data_dims[2] {
+        for by in 0..new_data_dims[1] {
+        for bx in 0..new_data_dims[0] {
+
+            shrunk.data[bx + by * new_data_dims[0] + bz * new_data_dims[0] * new_data_dims[1]] =
+                moved.data[bx + by * old_data_dims[0] + bz * old_data_dims[0] * old_data_dims[1]];
+
+        }}}
+
+        shrunk.clear_padding();
+
+        (shrunk, min)
+    }
+
+    // Moves every byte (2 voxels per byte along each axis)
+    fn shift_bytes(&self, byte_offset : [i32 ; 3])
+        -> BitVoxels
+    {
+        let data_dims = self.data_dims();
+        let signed_dims = [data_dims[0] as i32, data_dims[1] as i32, data_dims[2] as i32];
+
+        let mut shifted = BitVoxels::empty(self.dims);
+
+        for bz in 0..signed_dims[2] {
+        for by in 0..signed_dims[1] {
+        for bx in 0..signed_dims[0] {
+
+            let source = [bx - byte_offset[0], by - byte_offset[1], bz - byte_offset[2]];
+
+            if source.iter().zip(signed_dims.iter()).any(|(&c, &d)| c < 0 || c >= d)
+            {
+                continue;
+            }
+
+            let index = |c : [i32 ; 3]| (c[0] + c[1] * signed_dims[0] + c[2] * signed_dims[0] * signed_dims[1]) as usize;
+
+            shifted.data[index([bx, by, bz])] = self.data[index(source)];
+
+        }}}
+
+        shifted
+    }
+
+    // Moves every voxel by one along an axis.
+    // Half of a byte's bits stay in the byte, the other half come from the neighboring byte.
+    fn step(&self, axis : usize, positive : bool)
+        -> BitVoxels
+    {
+        let data_dims = self.data_dims();
+        let bit_stride = 1 << axis;
+        let byte_stride = [1, data_dims[0], data_dims[0] * data_dims[1]][axis];
+
+        let low = LOW_BITS[axis];
+        let high = !low;
+
+        let mut stepped = BitVoxels::empty(self.dims);
+
+        for (index, byte) in stepped.data.iter_mut().enumerate()
+        {
+            let axis_coord = (index / byte_stride) % data_dims[axis];
+
+            *byte = 
+                if positive
+                {
+                    let previous = if axis_coord > 0 {self.data[index - byte_stride]} else {0};
+
+                    ((self.data[index] & low) << bit_stride) | ((previous & high) >> bit_stride)
+                }
+                else
+                {
+                    let next = if axis_coord + 1 < data_dims[axis] {self.data[index + byte_stride]} else {0};
+
+                    ((self.data[index] & high) >> bit_stride) | ((next & low) << bit_stride)
+                };
+        }
+
+        stepped.clear_padding();
+
+        stepped
+    }
+
+    // Clears bits of bytes that are past odd dims
+    fn clear_padding(&mut self)
+    {
+        let data_dims = self.data_dims();
+
+        for axis in 0..3
+        {
+            if self.dims[axis] % 2 == 0
+            {
+                continue;
+            }
+
+            let byte_stride = [1, data_dims[0], data_dims[0] * data_dims[1]][axis];
+
+            for (index, byte) in self.data.iter_mut().enumerate()
+            {
+                if (index / byte_stride) % data_dims[axis] == data_dims[axis] - 1
+                {
+                    *byte &= LOW_BITS[axis];
+                }
+            }
+        }
+    }
 }
@@ -1,9 +1,13 @@
 use dot_vox as dv;
 use dv::DotVoxData;
 use std::mem;
+use nalgebra as na;
+use super::vox_scene::{VoxScene, ModelInstance};
+use super::VoxelMaterial;
 pub struct DotVoxWrapper
 {
-    vox_data : DotVoxData
+    vox_data : DotVoxData,
+    scene : Option<VoxScene>,
 }
 
 impl DotVoxWrapper
@@ -12,7 +16,17 @@ impl DotVoxWrapper
     pub fn new(file : &str)
         -> DotVoxWrapper
     {
-        let mut vox_data = dv::load(file).unwrap();
+        DotVoxWrapper::load(file).unwrap()
+    }
+
+    pub fn load(file : &str)
+        -> Result<DotVoxWrapper, String>
+    {
+        // dot_vox can panic on truncated files (e.g. read while still being saved)
+        let mut vox_data = 
+            std::panic::catch_unwind(|| dv::load(file))
+            .map_err(|_| format!("{}: Failed to parse file", file))?
+            .map_err(|err| format!("{}: {}", file, err))?;
 
         // change orientation (switches y and z)
         for model in &mut vox_data.models
@@ -25,8 +39,9 @@ impl DotVoxWrapper
             mem::swap(&mut model.size.y, &mut model.size.z);
         }
         
+        let scene = VoxScene::load(file);
 
-        DotVoxWrapper {vox_data}
+        Ok(DotVoxWrapper {vox_data, scene})
     }
     pub fn get_voxel(&self, coords : [usize ; 3], model_index : usize)
         -> Option<&dv::Voxel>
@@ -57,7 +72,57 @@ impl DotVoxWrapper
         return array;
     }
 
-    
+    // Material ids are 1-based, like the palette indices of the file
+    pub fn materials(&self)
+        -> [VoxelMaterial ; 256]
+    {
+        let mut materials = [VoxelMaterial::diffuse() ; 256];
+
+        for material in &self.vox_data.materials
+        {
+            if material.id == 0 || material.id > 256
+            {
+                continue;
+            }
+
+            materials[(material.id - 1) as usize] = DotVoxWrapper::parse_material(&material.properties);
+        }
+
+        materials
+    }
+
+    // Handles both the current property names (_emit, _trans, _metal)
+    // and the older format where _weight is used based on the _type
+    fn parse_material(properties : &dv::Dict)
+        -> VoxelMaterial
+    {
+        let property = |key : &str| 
+            properties.get(key).and_then(|value| value.parse::<f32>().ok());
+
+        let material_type = properties.get("_type").map(|t| t.as_str()).unwrap_or("_diffuse");
+        let weight = property("_weight").unwrap_or(0.0);
+
+        let typed_weight = |t : &str| if material_type == t {weight} else {0.0};
+
+        let mut material = VoxelMaterial::diffuse();
+
+        if material_type == "_emit"
+        {
+            // flux is a power of ten
+            material.emission = 
+                property("_emit").unwrap_or(weight) 
+                * 10f32.powf(property("_flux").unwrap_or(0.0));
+        }
+
+        material.transparency = property("_trans").unwrap_or(typed_weight("_glass"));
+        material.metal = property("_metal").unwrap_or(typed_weight("_metal"));
+        material.roughness = property("_rough").unwrap_or(material.roughness);
+        // stored as the offset from 1
+        material.ior = 1.0 + property("_ior").unwrap_or(0.0);
+
+        material
+    }
+
     pub fn voxel_slice(&self, model_index : usize)
         -> &[dv::Voxel]
     {
@@ -71,4 +136,80 @@ impl DotVoxWrapper
 
         [size.x  as usize, size.y as usize, size.z as usize]
     }
-}
\ No newline at end of file
+
+    pub fn model_count(&self)
+        -> usize
+    {
+        self.vox_data.models.len()
+    }
+
+    // Every placed model of the scene graph.
+    // Files without a scene graph place each model once, untransformed.
+    pub fn model_instances(&self)
+        -> Vec<ModelInstance>
+    {
+        match &self.scene
+        {
+            Some(scene) => 
+                scene.model_instances().into_iter()
+                .filter(|instance| instance.model_index < self.model_count())
+                .collect(),
+            None => 
+                (0..self.model_count())
+                .map(|model_index| ModelInstance {
+                    model_index, translation : na::zero(), rotation : na::Matrix3::identity()})
+                .collect(),
+        }
+    }
+
+    // All model instances merged into one volume (y up, like the rest of the engine).
+    // Returns the dims of the volume and every voxel's coords and palette index.
+    pub fn composite_voxels(&self)
+        -> ([usize ; 3], Vec<([usize ; 3], u8)>)
+    {
+        let mut placed = Vec::new();
+
+        for instance in self.model_instances()
+        {
+            let model = &self.vox_data.models[instance.model_index];
+
+            // Scene transforms are in MagicaVoxel space, so y and z are switched back
+            let size = na::Vector3::new(model.size.x as i32, model.size.z as i32, model.size.y as i32);
+
+            for voxel in &model.voxels
+            {
+                let local = na::Vector3::new(voxel.x as i32, voxel.z as i32, voxel.y as i32);
+
+                // The model is rotated about its center,
+                // doubled coordinates keep the half voxel offset an integer
+                let doubled = instance.rotation * (local * 2 + na::Vector3::repeat(1) - size)
+                    + instance.translation * 2;
+                let world = doubled.map(|c| c.div_euclid(2));
+
+                placed.push((na::Vector3::new(world.x, world.z, world.y), voxel.i));
+            }
+        }
+
+        if placed.is_empty()
+        {
+            return ([0 ; 3], Vec::new());
+        }
+
+        let min = placed.iter().fold(placed[0].0, |acc, (pos, _)| acc.zip_map(pos, |a, b| a.min(b)));
+        let max = placed.iter().fold(placed[0].0, |acc, (pos, _)| acc.zip_map(pos, |a, b| a.max(b)));
+
+        let extent = max - min + na::Vector3::repeat(1);
+        let dims = [extent.x as usize, extent.y as usize, extent.z as usize];
+
+        let voxels = 
+            placed.into_iter()
+            .map(|(pos, i)| 
+            {
+                let offset = pos - min;
+                ([offset.x as usize, offset.y as usize, offset.z as usize], i)
+            })
+            .collect();
+
+        (dims, voxels)
+    }
+}
@@ -0,0 +1,187 @@
+use nalgebra as na;
+
+use super::VoxelPrefab;
+use super::byte_voxels::BitVoxels;
+
+// Draws prefabs on the cpu as isometric icons, seen from the +x, +y, +z corner.
+// Rays are cast through the bit volume, so no gpu or window is needed.
+pub struct IconRenderer
+{
+    pub size : u32,
+    // The direction towards the light
+    pub light_dir : na::Vector3<f32>,
+    pub ambient : f32,
+    // Rays per pixel along each axis, for smooth edges
+    pub supersampling : u32,
+}
+
+impl IconRenderer
+{
+    pub fn new(size : u32)
+        -> IconRenderer
+    {
+        IconRenderer
+        {
+            size,
+            light_dir : na::Vector3::new(0.5, 1.0, 0.25).normalize(),
+            ambient : 0.35,
+            supersampling : 3,
+        }
+    }
+
+    pub fn render(&self, prefab : &dyn VoxelPrefab)
+        -> image::RgbaImage
+    {
+        let bit_voxels = prefab.bit_voxels();
+        let palette_volume = prefab.palette_volume();
+        let palette = prefab.palette();
+
+        let dims = bit_voxels.dims();
+        let dims_f = na::Vector3::new(dims[0] as f32, dims[1] as f32, dims[2] as f32);
+
+        let forward = na::Vector3::new(-1.0, -1.0, -1.0).normalize();
+        let right = forward.cross(&na::Vector3::y()).normalize();
+        let up = right.cross(&forward);
+
+        // The whole volume fits in the icon from any corner
+        let span = dims_f.norm();
+        let center = dims_f / 2.0;
+
+        let samples = self.supersampling.max(1);
+
+        image::RgbaImage::from_fn(self.size, self.size, |px, py|
+        {
+            let mut color_sum = na::Vector3::<f32>::zeros();
+            let mut hits = 0;
+
+            for sx in 0..samples {
+            for sy in 0..samples {
+
+                let u = ((px * samples + sx) as f32 + 0.5) / (self.size * samples) as f32 - 0.5;
+                let v = 0.5 - ((py * samples + sy) as f32 + 0.5) / (self.size * samples) as f32;
+
+                let origin = center + (right * u + up * v) * span - forward * span;
+
+                if let Some((coords, normal)) = cast_ray(bit_voxels, origin, forward)
+                {
+                    let palette_index = palette_volume[coords[0] + coords[1] * dims[0] + coords[2] * dims[0] * dims[1]];
+                    let bytes = palette[palette_index as usize].to_le_bytes();
+
+                    let albedo = na::Vector3::new(bytes[0] as f32, bytes[1] as f32, bytes[2] as f32);
+
+                    let shade = self.ambient + (1.0 - self.ambient) * normal.dot(&self.light_dir).max(0.0);
+
+                    color_sum += albedo * shade;
+                    hits += 1;
+                }
+
+            }}
+
+            if hits == 0
+            {
+                return image::Rgba([0 ; 4]);
+            }
+
+            let color = color_sum / hits as f32;
+            let alpha = 255.0 * hits as f32 / (samples * samples) as f32;
+
+            image::Rgba([color.x.min(255.0) as u8, color.y.min(255.0) as u8, color.z.min(255.0) as u8, alpha as u8])
+        })
+    }
+
+    pub fn save(&self, prefab : &dyn VoxelPrefab, path : &str)
+        -> image::ImageResult<()>
+    {
+        self.render(prefab).save(path)
+    }
+}
+
+// Steps through the volume voxel by voxel (Amanatides & Woo).
+// Returns the first present voxel and the normal of the face the ray entered through.
+fn cast_ray(bit_voxels : &BitVoxels, origin : na::Vector3<f32>, dir : na::Vector3<f32>)
+    -> Option<([usize ; 3], na::Vector3<f32>)>
+{
+    let dims = bit_voxels.dims();
+
+    // slab intersection with the volume's bounds
+    let mut t_enter = std::f32::NEG_INFINITY;
+    let mut t_exit = std::f32::INFINITY;
+    let mut axis = 0;
+
+    for i in 0..3
+    {
+        if dir[i] == 0.0
+        {
+            if origin[i] < 0.0 || origin[i] > dims[i] as f32
+            {
+                return None;
+            }
+            continue;
+        }
+
+        let t0 = (0.0 - origin[i]) / dir[i];
+        let t1 = (dims[i] as f32 - origin[i]) / dir[i];
+
+        if t0.min(t1) > t_enter
+        {
+            t_enter = t0.min(t1);
+            axis = i;
+        }
+        t_exit = t_exit.min(t0.max(t1));
+    }
+
+    if t_exit < t_enter.max(0.0)
+    {
+        return None;
+    }
+
+    let entry = origin + dir * t_enter.max(0.0);
+
+    let mut voxel = [0i32 ; 3];
+    let mut step = [0i32 ; 3];
+    let mut t_max = [std::f32::INFINITY ; 3];
+    let mut t_delta = [std::f32::INFINITY ; 3];
+
+    for i in 0..3
+    {
+        voxel[i] = (entry[i].floor() as i32).max(0).min(dims[i] as i32 - 1);
+
+        if dir[i] > 0.0
+        {
+            step[i] = 1;
+            t_max[i] = t_enter.max(0.0) + ((voxel[i] + 1) as f32 - entry[i]) / dir[i];
+            t_delta[i] = 1.0 / dir[i];
+        }
+        else if dir[i] < 0.0
+        {
+            step[i] = -1;
+            t_max[i] = t_enter.max(0.0) + (voxel[i] as f32 - entry[i]) / dir[i];
+            t_delta[i] = -1.0 / dir[i];
+        }
+    }
+
+    loop
+    {
+        if bit_voxels.get_voxel([voxel[0] as usize, voxel[1] as usize, voxel[2] as usize])
+        {
+            let mut normal = na::Vector3::zeros();
+            normal[axis] = -step[axis] as f32;
+
+            return Some(([voxel[0] as usize, voxel[1] as usize, voxel[2] as usize], normal));
+        }
+
+        axis =
+            if t_max[0] < t_max[1] && t_max[0] < t_max[2] {0}
+            else if t_max[1] < t_max[2] {1}
+            else {2};
+
+        voxel[axis] += step[axis];
+
+        if voxel[axis] < 0 || voxel[axis] >= dims[axis] as i32
+        {
+            return None;
+        }
+
+        t_max[axis] += t_delta[axis];
+    }
+}
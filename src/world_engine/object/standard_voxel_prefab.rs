@@ -1,13 +1,24 @@
 use super::byte_voxels::BitVoxels;
 use super::dot_vox_wrapper::DotVoxWrapper;
+use super::VoxelMaterial;
+use super::voxel_normals;
+use super::super::data_structures::SESVOctree;
 use nalgebra as na;
+use std::collections::BTreeMap;
+
+// The width of a prefab in voxels, prefabs are cubes
+pub const PREFAB_WIDTH : usize = 32;
 
 pub struct StandardVoxelPrefab
 {
     dims : [usize ; 3],
     bit_voxels : BitVoxels,
     palette_volume : Vec<u8>,
-    palette : [u32 ; 256]
+    normal_volume : Vec<u32>,
+    // Built once, with the most common palette index of each node for distant lods
+    tree : SESVOctree,
+    palette : [u32 ; 256],
+    materials : [VoxelMaterial ; 256],
 }
 
 
@@ -16,37 +27,121 @@ impl StandardVoxelPrefab
     pub fn new(vox_file_path : &str)
         -> StandardVoxelPrefab
     {
-        let vox_data_wrap = DotVoxWrapper::new(vox_file_path);
+        StandardVoxelPrefab::load(vox_file_path).unwrap()
+    }
+
+    pub fn load(vox_file_path : &str)
+        -> Result<StandardVoxelPrefab, String>
+    {
+        let vox_data_wrap = DotVoxWrapper::load(vox_file_path)?;
 
-        let bit_voxels = BitVoxels::new(&vox_data_wrap, 0);
+        if vox_data_wrap.model_count() == 0
+        {
+            return Err(format!("{}: No models in file!", vox_file_path));
+        }
 
         let dims = vox_data_wrap.dims(0);
 
-        if dims[0] != 32 || dims[1] != 32 || dims[2] != 32
+        if dims[0] != PREFAB_WIDTH || dims[1] != PREFAB_WIDTH || dims[2] != PREFAB_WIDTH
         {
-            panic!("Must have appropriately dimensioned model!");
+            return Err(format!("{}: Must have appropriately dimensioned model!", vox_file_path));
         }
 
-        let palette_volume = 
+        let voxels = 
+            vox_data_wrap.voxel_slice(0).iter()
+            .map(|voxel| ([voxel.x as usize, voxel.y as usize, voxel.z as usize], voxel.i));
+
+        Ok(StandardVoxelPrefab::from_voxels(voxels, vox_data_wrap.palette(), vox_data_wrap.materials()))
+    }
+
+    // Voxel coords must be within the prefab's dims
+    pub fn from_voxels<I>(voxels : I, palette : [u32 ; 256], materials : [VoxelMaterial ; 256])
+        -> StandardVoxelPrefab
+        where I : Iterator<Item = ([usize ; 3], u8)>
+    {
+        let dims = [PREFAB_WIDTH ; 3];
+
+        let mut bit_voxels = BitVoxels::empty(dims);
+        let mut palette_volume = vec![0 ; dims[0] * dims[1] * dims[2]];
+
+        for (coords, palette_index) in voxels
         {
-            let mut pal_vol = vec![0 ; dims[0] * dims[1] * dims[2]];
-            for voxel in vox_data_wrap.voxel_slice(0)
+            bit_voxels.set_voxel(coords, true);
+
+            let index = 
+                coords[0]
+                + coords[1] * dims[0] 
+                + coords[2] * dims[0] * dims[1];
+            palette_volume[index] = palette_index;
+        }
+
+        let normal_volume = voxel_normals::normal_volume(&bit_voxels);
+
+        let tree = StandardVoxelPrefab::build_tree(&bit_voxels, &palette_volume);
+
+        StandardVoxelPrefab {dims, bit_voxels, palette_volume, normal_volume, tree, palette, materials}
+    }
+
+    fn build_tree(bit_voxels : &BitVoxels, palette_volume : &[u8])
+        -> SESVOctree
+    {
+        let mut tree = SESVOctree::new(na::Vector3::repeat(0).into(), 5);
+
+        for x in 0..PREFAB_WIDTH
+        {
+        for y in 0..PREFAB_WIDTH
+        {
+        for z in 0..PREFAB_WIDTH
+        {
+            if bit_voxels.get_voxel([x, y, z])
             {
-                let index = 
-                    voxel.x as usize 
-                    + voxel.y as usize * dims[0] 
-                    + voxel.z as usize * dims[0] * dims[1];
-                pal_vol[index] = voxel.i;
+                tree.insert_no_val(na::Point3::new(x as i32, y as i32, z as i32));
             }
-            pal_vol
-        };
+        }
+        }
+        }
+
+        tree.build_lod(|pos, _|
+            palette_volume[
+                pos.x as usize 
+                + pos.y as usize * PREFAB_WIDTH 
+                + pos.z as usize * PREFAB_WIDTH * PREFAB_WIDTH]);
+
+        tree
+    }
+
+    // The whole scene of a file (every model, with its transform) is merged
+    // and cut into prefab sized blocks.
+    // Each non empty block is returned with its block offset from the scene's minimum corner.
+    pub fn from_scene(vox_file_path : &str)
+        -> Vec<(na::Vector3<i32>, StandardVoxelPrefab)>
+    {
+        let vox_data_wrap = DotVoxWrapper::new(vox_file_path);
+
+        let (_, voxels) = vox_data_wrap.composite_voxels();
+
+        let mut blocks : BTreeMap<[usize ; 3], Vec<([usize ; 3], u8)>> = BTreeMap::new();
+
+        for (coords, palette_index) in voxels
+        {
+            let block = [coords[0] / PREFAB_WIDTH, coords[1] / PREFAB_WIDTH, coords[2] / PREFAB_WIDTH];
+            let coords_in_block = [coords[0] % PREFAB_WIDTH, coords[1] % PREFAB_WIDTH, coords[2] % PREFAB_WIDTH];
+
+            blocks.entry(block).or_insert_with(Vec::new).push((coords_in_block, palette_index));
+        }
 
         let palette = vox_data_wrap.palette();
+        let materials = vox_data_wrap.materials();
 
-        StandardVoxelPrefab {dims, bit_voxels, palette_volume, palette}
+        blocks.into_iter()
+        .map(|(block, block_voxels)| 
+            (
+                na::Vector3::new(block[0] as i32, block[1] as i32, block[2] as i32),
+                StandardVoxelPrefab::from_voxels(block_voxels.into_iter(), palette, materials)
+            ))
+        .collect()
     }
 }
-use super::super::super::world_engine::data_structures::SESVOctree;
 
 
 
@@ -57,30 +152,29 @@ impl super::VoxelPrefab for StandardVoxelPrefab
     {
         self.palette_volume.clone()
     }
+    fn normal_volume(&self)
+        -> Vec<u32>
+    {
+        self.normal_volume.clone()
+    }
     fn palette(&self)
         -> [u32 ; 256]
     {
         self.palette
     }
+    fn materials(&self)
+        -> [VoxelMaterial ; 256]
+    {
+        self.materials
+    }
+    fn bit_voxels(&self)
+        -> &BitVoxels
+    {
+        &self.bit_voxels
+    }
     fn tree_volume(&self) 
         -> SESVOctree 
     {
-        let mut tree = SESVOctree::new(na::Vector3::repeat(0).into(), 5);
-
-        for x in 0..32
-        {
-        for y in 0..32
-        {
-        for z in 0..32
-        {
-            if self.bit_voxels.get_voxel([x, y, z])
-            {
-                tree.insert_no_val(na::Point3::new(x as i32, y as i32, z as i32));
-            }
-        }
-        }
-        }
-        
-        tree
+        self.tree.clone()
     }
 }
\ No newline at end of file
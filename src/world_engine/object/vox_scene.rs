@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+
+use nalgebra as na;
+
+// The scene graph of a MagicaVoxel file.
+// dot_vox only reads models and palettes, so the nTRN/nGRP/nSHP chunks
+// are read here directly from the file bytes.
+pub struct VoxScene
+{
+    nodes : HashMap<u32, SceneNode>,
+}
+
+enum SceneNode
+{
+    Transform {child : u32, translation : na::Vector3<i32>, rotation : na::Matrix3<i32>},
+    Group {children : Vec<u32>},
+    Shape {models : Vec<u32>},
+}
+
+// A model placed in the scene, in MagicaVoxel coordinates (z is up)
+#[derive(Clone, Debug)]
+pub struct ModelInstance
+{
+    pub model_index : usize,
+    pub translation : na::Vector3<i32>,
+    pub rotation : na::Matrix3<i32>,
+}
+
+const ROOT_NODE : u32 = 0;
+
+impl VoxScene
+{
+    // returns none if the file has no scene graph (older .vox versions)
+    pub fn load(file : &str)
+        -> Option<VoxScene>
+    {
+        let bytes = std::fs::read(file).ok()?;
+
+        VoxScene::from_bytes(&bytes)
+    }
+
+    pub fn from_bytes(bytes : &[u8])
+        -> Option<VoxScene>
+    {
+        let mut reader = ByteReader {bytes, pos : 0};
+
+        if reader.take(4)? != b"VOX "
+        {
+            return None;
+        }
+
+        let _version = reader.u32()?;
+
+        // MAIN chunk header, its children are every other chunk
+        if reader.take(4)? != b"MAIN"
+        {
+            return None;
+        }
+        let main_content_size = reader.u32()? as usize;
+        let _main_children_size = reader.u32()?;
+        reader.take(main_content_size)?;
+
+        let mut nodes = HashMap::new();
+
+        while reader.remaining() >= 12
+        {
+            let id = reader.take(4)?;
+            let content_size = reader.u32()? as usize;
+            let children_size = reader.u32()? as usize;
+            let content = reader.take(content_size)?;
+            reader.take(children_size)?;
+
+            let mut content_reader = ByteReader {bytes : content, pos : 0};
+
+            let parsed = match id
+            {
+                b"nTRN" => VoxScene::parse_transform(&mut content_reader),
+                b"nGRP" => VoxScene::parse_group(&mut content_reader),
+                b"nSHP" => VoxScene::parse_shape(&mut content_reader),
+                _ => continue,
+            };
+
+            let (node_id, node) = parsed?;
+            nodes.insert(node_id, node);
+        }
+
+        if !nodes.contains_key(&ROOT_NODE)
+        {
+            return None;
+        }
+
+        Some(VoxScene {nodes})
+    }
+
+    fn parse_transform(reader : &mut ByteReader)
+        -> Option<(u32, SceneNode)>
+    {
+        let node_id = reader.u32()?;
+        let _attributes = reader.dict()?;
+        let child = reader.u32()?;
+        let _reserved = reader.u32()?;
+        let _layer = reader.u32()?;
+        let frame_count = reader.u32()?;
+
+        let mut translation = na::Vector3::zeros();
+        let mut rotation = na::Matrix3::identity();
+
+        // Only the first frame is used, animation isn't supported
+        for frame in 0..frame_count
+        {
+            let attributes = reader.dict()?;
+
+            if frame != 0
+            {
+                continue;
+            }
+
+            if let Some(t) = attributes.get("_t")
+            {
+                let comps : Vec<i32> = t.split_whitespace().filter_map(|c| c.parse().ok()).collect();
+
+                if comps.len() == 3
+                {
+                    translation = na::Vector3::new(comps[0], comps[1], comps[2]);
+                }
+            }
+
+            if let Some(r) = attributes.get("_r")
+            {
+                rotation = rotation_from_byte(r.parse().ok()?);
+            }
+        }
+
+        Some((node_id, SceneNode::Transform {child, translation, rotation}))
+    }
+
+    fn parse_group(reader : &mut ByteReader)
+        -> Option<(u32, SceneNode)>
+    {
+        let node_id = reader.u32()?;
+        let _attributes = reader.dict()?;
+        let child_count = reader.u32()?;
+
+        let children = (0..child_count).map(|_| reader.u32()).collect::<Option<Vec<u32>>>()?;
+
+        Some((node_id, SceneNode::Group {children}))
+    }
+
+    fn parse_shape(reader : &mut ByteReader)
+        -> Option<(u32, SceneNode)>
+    {
+        let node_id = reader.u32()?;
+        let _attributes = reader.dict()?;
+        let model_count = reader.u32()?;
+
+        let mut models = Vec::new();
+
+        for _ in 0..model_count
+        {
+            models.push(reader.u32()?);
+            let _model_attributes = reader.dict()?;
+        }
+
+        Some((node_id, SceneNode::Shape {models}))
+    }
+
+    // Every model in the scene with its accumulated transform
+    pub fn model_instances(&self)
+        -> Vec<ModelInstance>
+    {
+        let mut instances = Vec::new();
+
+        self.collect_instances(ROOT_NODE, na::zero(), na::Matrix3::identity(), &mut instances, 0);
+
+        instances
+    }
+
+    fn collect_instances(&self,
+        node_id : u32,
+        translation : na::Vector3<i32>,
+        rotation : na::Matrix3<i32>,
+        instances : &mut Vec<ModelInstance>,
+        depth : usize)
+    {
+        // guards against cyclic node references in malformed files
+        if depth > self.nodes.len()
+        {
+            return;
+        }
+
+        match self.nodes.get(&node_id)
+        {
+            Some(SceneNode::Transform {child, translation : local_t, rotation : local_r}) =>
+            {
+                self.collect_instances(*child,
+                    rotation * local_t + translation,
+                    rotation * local_r,
+                    instances, depth + 1);
+            },
+            Some(SceneNode::Group {children}) =>
+            {
+                for &child in children
+                {
+                    self.collect_instances(child, translation, rotation, instances, depth + 1);
+                }
+            },
+            Some(SceneNode::Shape {models}) =>
+            {
+                instances.extend(
+                    models.iter().map(|&model|
+                        ModelInstance {model_index : model as usize, translation, rotation}));
+            },
+            None => (),
+        }
+    }
+}
+
+// The rotation is packed into a byte:
+// bits 0-1 => column of the non zero entry in the first row
+// bits 2-3 => column of the non zero entry in the second row
+// bits 4, 5, 6 => the sign of the entry in the first, second and third row
+fn rotation_from_byte(byte : u8)
+    -> na::Matrix3<i32>
+{
+    let first = (byte & 3) as usize;
+    let second = ((byte >> 2) & 3) as usize;
+    let third = 3usize.saturating_sub(first + second).min(2);
+
+    let mut rotation = na::Matrix3::zeros();
+
+    for (row, &column) in [first, second, third].iter().enumerate()
+    {
+        let sign = if (byte >> (4 + row)) & 1 == 1 {-1} else {1};
+        rotation[(row, column)] = sign;
+    }
+
+    rotation
+}
+
+struct ByteReader<'a>
+{
+    bytes : &'a [u8],
+    pos : usize,
+}
+
+impl<'a> ByteReader<'a>
+{
+    fn remaining(&self)
+        -> usize
+    {
+        self.bytes.len() - self.pos
+    }
+
+    fn take(&mut self, len : usize)
+        -> Option<&'a [u8]>
+    {
+        if self.remaining() < len
+        {
+            return None;
+        }
+
+        let slice = &self.bytes[self.pos..(self.pos + len)];
+        self.pos += len;
+
+        Some(slice)
+    }
+
+    fn u32(&mut self)
+        -> Option<u32>
+    {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn string(&mut self)
+        -> Option<String>
+    {
+        let len = self.u32()? as usize;
+
+        String::from_utf8(self.take(len)?.to_vec()).ok()
+    }
+
+    fn dict(&mut self)
+        -> Option<HashMap<String, String>>
+    {
+        let entry_count = self.u32()?;
+
+        (0..entry_count)
+        .map(|_| Some((self.string()?, self.string()?)))
+        .collect()
+    }
+}
@@ -0,0 +1,177 @@
+use dot_vox as dv;
+use std::collections::HashMap;
+use std::io;
+
+use super::byte_voxels::BitVoxels;
+use super::VoxelPrefab;
+use super::super::block_state::BlockOrientation;
+
+// The largest model MagicaVoxel can open
+pub const MAX_MODEL_WIDTH : usize = 256;
+
+// Only 255 colors can be referenced by a voxel
+const MAX_PALETTE_LEN : usize = 255;
+
+// Collects colored voxels and saves them as a single model MagicaVoxel file.
+// Coordinates are in engine space (y is up) and are converted on save.
+pub struct VoxWriter
+{
+    dims : [usize ; 3],
+    voxels : Vec<([usize ; 3], u32)>,
+}
+
+impl VoxWriter
+{
+    pub fn new(dims : [usize ; 3])
+        -> VoxWriter
+    {
+        VoxWriter {dims, voxels : Vec::new()}
+    }
+
+    pub fn set_voxel(&mut self, coords : [usize ; 3], color : u32)
+    {
+        self.voxels.push((coords, color));
+    }
+
+    // Adds a palette indexed volume, placed at the offset
+    pub fn insert_volume(&mut self,
+        offset : [usize ; 3],
+        bit_voxels : &BitVoxels,
+        palette_volume : &[u8],
+        palette : &[u32 ; 256])
+    {
+        let dims = bit_voxels.dims();
+
+        for x in 0..dims[0] {
+        for y in 0..dims[1] {
+        for z in 0..dims[2] {
+
+            if !bit_voxels.get_voxel([x, y, z])
+            {
+                continue;
+            }
+
+            let palette_index = palette_volume[x + y * dims[0] + z * dims[0] * dims[1]];
+
+            self.set_voxel(
+                [offset[0] + x, offset[1] + y, offset[2] + z],
+                palette[palette_index as usize]);
+
+        }}}
+    }
+
+    // Adds a prefab (a cube) turned to the orientation
+    pub fn insert_prefab(&mut self, offset : [usize ; 3], prefab : &dyn VoxelPrefab, orientation : BlockOrientation)
+    {
+        let bit_voxels = prefab.bit_voxels();
+        let palette_volume = prefab.palette_volume();
+        let palette = prefab.palette();
+
+        let width = bit_voxels.dims()[0];
+
+        for x in 0..width {
+        for y in 0..width {
+        for z in 0..width {
+
+            if !bit_voxels.get_voxel([x, y, z])
+            {
+                continue;
+            }
+
+            let palette_index = palette_volume[x + y * width + z * width * width];
+
+            let block_coords = orientation.to_block_coords([x, y, z], width);
+
+            self.set_voxel(
+                [offset[0] + block_coords[0], offset[1] + block_coords[1], offset[2] + block_coords[2]],
+                palette[palette_index as usize]);
+
+        }}}
+    }
+
+    pub fn save(&self, file : &str)
+        -> io::Result<()>
+    {
+        if self.dims.iter().any(|&d| d == 0 || d > MAX_MODEL_WIDTH)
+        {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                format!("Model dims {:?} can't be saved as a .vox model", self.dims)));
+        }
+
+        let (palette, color_indices) = self.build_palette();
+
+        let voxels =
+            self.voxels.iter()
+            .filter(|(coords, _)| coords.iter().zip(self.dims.iter()).all(|(c, d)| c < d))
+            // switches y and z back to MagicaVoxel's orientation
+            .map(|(coords, color)| dv::Voxel {
+                x : coords[0] as u8,
+                y : coords[2] as u8,
+                z : coords[1] as u8,
+                i : color_indices[color],
+            })
+            .collect();
+
+        let size = dv::Size {x : self.dims[0] as u32, y : self.dims[2] as u32, z : self.dims[1] as u32};
+
+        let vox_data = dv::DotVoxData
+        {
+            version : 150,
+            models : vec!(dv::Model {size, voxels}),
+            palette,
+            materials : Vec::new(),
+        };
+
+        let mut writer = io::BufWriter::new(std::fs::File::create(file)?);
+
+        vox_data.write_vox(&mut writer)
+    }
+
+    // Gives each distinct color a palette index.
+    // Colors past the palette limit use the index of the closest existing color.
+    fn build_palette(&self)
+        -> (Vec<u32>, HashMap<u32, u8>)
+    {
+        let mut palette = Vec::new();
+        let mut color_indices = HashMap::new();
+
+        for &(_, color) in &self.voxels
+        {
+            if color_indices.contains_key(&color)
+            {
+                continue;
+            }
+
+            let index =
+                if palette.len() < MAX_PALETTE_LEN
+                {
+                    palette.push(color);
+                    palette.len() - 1
+                }
+                else
+                {
+                    closest_color_index(&palette, color)
+                };
+
+            color_indices.insert(color, index as u8);
+        }
+
+        palette.resize(256, 0);
+
+        (palette, color_indices)
+    }
+}
+
+fn closest_color_index(palette : &[u32], color : u32)
+    -> usize
+{
+    let channel_distance = |a : u32, b : u32|
+        a.to_le_bytes().iter().zip(b.to_le_bytes().iter())
+        .take(3)
+        .map(|(&ca, &cb)| (ca as i32 - cb as i32).pow(2))
+        .sum::<i32>();
+
+    (0..palette.len())
+    .min_by_key(|&i| channel_distance(palette[i], color))
+    .unwrap_or(0)
+}
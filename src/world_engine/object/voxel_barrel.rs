@@ -1,20 +1,46 @@
 pub struct VoxelBarrel
 {
     dims : [usize ; 3],
-    top_texture : Vec<u32>,
-    side_front_texture : Vec<u32>,
-    side_right_texture : Vec<u32>,
-    bottom_texture : Vec<u32>
+    projections : [FaceProjection ; 6],
+    // One texture per face (top, front, right, left, back, bottom)
+    face_textures : [Vec<u32> ; 6],
 }
 
 use super::dot_vox_wrapper::DotVoxWrapper;
 use super::byte_voxels::BitVoxels;
 use super::VoxFace;
 
+// How a face's texture is projected, see get_ortho_texture
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FaceProjection
+{
+    pub face : VoxFace,
+    pub flips : [bool ; 3],
+}
+
+// Textures read left to right and top to bottom, as seen from outside the model
+pub const DEFAULT_PROJECTIONS : [FaceProjection ; 6] =
+[
+    FaceProjection {face : VoxFace::XZ, flips : [false, true, true]}, // top
+    FaceProjection {face : VoxFace::XY, flips : [false, true, false]}, // front
+    FaceProjection {face : VoxFace::ZY, flips : [false, true, true]}, // right
+    FaceProjection {face : VoxFace::ZY, flips : [true, true, false]}, // left
+    FaceProjection {face : VoxFace::XY, flips : [true, true, true]}, // back
+    FaceProjection {face : VoxFace::XZ, flips : [true, true, false]}, // bottom
+];
+
+const FACE_NAMES : [&str ; 6] = ["top", "front", "right", "left", "back", "bottom"];
+
 impl VoxelBarrel
 {
     pub fn new(vox_file_path : &str)
         -> VoxelBarrel
+    {
+        VoxelBarrel::with_projections(vox_file_path, DEFAULT_PROJECTIONS)
+    }
+
+    pub fn with_projections(vox_file_path : &str, projections : [FaceProjection ; 6])
+        -> VoxelBarrel
     {
         let vox_data_wrap = DotVoxWrapper::new(vox_file_path);
 
@@ -22,41 +48,40 @@ impl VoxelBarrel
 
         let dims = vox_data_wrap.dims(0);
 
-        // let slice = get_2D_slice(&model, VoxFace::XZ, 1);
-        let side_front_texture = super::get_ortho_texture(&volume, &vox_data_wrap, 0, VoxFace::XY, [false, true, false]);
-        let top_texture = super::get_ortho_texture(&volume, &vox_data_wrap, 0, VoxFace::XZ, [false, true, true]);
-        let bottom_texture = super::get_ortho_texture(&volume, &vox_data_wrap, 0, VoxFace::XZ, [true, true, false]);
-        let side_right_texture = super::get_ortho_texture(&volume, &vox_data_wrap, 0, VoxFace::ZY, [false, true, true]);
-        // let side_left_texture = get_ortho_texture(&volume, &vox_data_wrap, 0, VoxFace::ZY, [true, true, false]);
-        // let side_back_texture = get_ortho_texture(&volume, &vox_data_wrap, 0, VoxFace::XY, [true, true, true]);
-        // let side_four_texture = get_ortho_texture(&volume, &vox_data, 0, VoxFace::YZ, false);
+        let project = |i : usize|
+            super::get_ortho_texture(&volume, &vox_data_wrap, 0, projections[i].face, projections[i].flips);
 
+        let face_textures = [project(0), project(1), project(2), project(3), project(4), project(5)];
 
-        // VoxBarrel::save_texture(24, 24, 
-        //     &side_front_texture, "side");
+        VoxelBarrel {dims, projections, face_textures}
+    }
 
-        VoxelBarrel {dims, top_texture, side_front_texture, side_right_texture, bottom_texture}
+    // The texture of a face (top, front, right, left, back, bottom) and its width and height
+    pub fn face_texture(&self, face_index : usize)
+        -> (&Vec<u32>, [usize ; 2])
+    {
+        (&self.face_textures[face_index], self.projections[face_index].face.texture_dims(self.dims))
     }
-    
 
-    pub fn save_texture(width : u32, height : u32, texture : &Vec<u32>, name : &str)
+    // Saves every face as <name>_<face>.png
+    pub fn save_textures(&self, name : &str)
     {
-        // how do i do this via iterator?
-        let byte_texture : Vec<u8> = 
+        for (face_index, face_name) in FACE_NAMES.iter().enumerate()
         {
-            let mut result = Vec::new();
+            let (texture, [width, height]) = self.face_texture(face_index);
 
-            texture.iter().for_each( |color|
-                result.extend(
-                    color.to_le_bytes().iter()
-                )
-            );
+            VoxelBarrel::save_texture(width as u32, height as u32, texture, &format!("{}_{}", name, face_name));
+        }
+    }
 
-            result
-        };
+    pub fn save_texture(width : u32, height : u32, texture : &Vec<u32>, name : &str)
+    {
+        let byte_texture : Vec<u8> = texture.iter().flat_map(|color| color.to_le_bytes().to_vec()).collect();
 
         let image = image::ImageBuffer::<image::Rgba<u8>, _>::from_raw(width, height, byte_texture.as_slice()).unwrap();
 
+        std::fs::create_dir_all("resources/saved_test_textures").unwrap();
+
         image.save(format!("resources/saved_test_textures/{}.png", name)).unwrap();
     }
 }
@@ -72,21 +97,19 @@ impl super::TexturedVoxelPrefab for VoxelBarrel
     fn get_texture(&self)
         -> Vec<u32>
     {
-        self.top_texture.iter()
-        .chain(self.side_front_texture.iter())
-        .chain(self.side_right_texture.iter())
-        .chain(self.bottom_texture.iter())
+        self.face_textures.iter()
+        .flat_map(|texture| texture.iter())
         .cloned().collect()
     }
-    
+
 
     fn texture_count(&self)
         -> u32
-    {4}
+    {6}
 
     fn face_texture_offsets(&self)
         -> [u32 ; 6]
     {
-        [0, 1, 2, 2, 1, 3]
+        [0, 1, 2, 3, 4, 5]
     }
-}
\ No newline at end of file
+}
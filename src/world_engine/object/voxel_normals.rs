@@ -0,0 +1,117 @@
+use nalgebra as na;
+
+use super::byte_voxels::BitVoxels;
+
+// How far around a voxel is looked at for its normal.
+// A wider neighborhood gives smoother normals on stair stepped surfaces.
+const GRADIENT_RADIUS : i32 = 2;
+
+// Set in the 4th byte of a packed normal for voxels on the surface
+const SURFACE_FLAG : u8 = 127;
+
+// A normal per voxel, laid out like a palette volume (x + y * width + z * width * height).
+// Packed as 4 signed bytes (x, y, z, surface flag) to be read as an R8G8B8A8Snorm texel.
+// Voxels that aren't on the surface get 0.
+pub fn normal_volume(bit_voxels : &BitVoxels)
+    -> Vec<u32>
+{
+    let dims = bit_voxels.dims();
+    let surface = bit_voxels.surface();
+
+    let mut normals = vec![0 ; dims[0] * dims[1] * dims[2]];
+
+    for x in 0..dims[0] {
+    for y in 0..dims[1] {
+    for z in 0..dims[2] {
+
+        if !surface.get_voxel([x, y, z])
+        {
+            continue;
+        }
+
+        let normal = estimate_normal(bit_voxels, [x, y, z]);
+
+        normals[x + y * dims[0] + z * dims[0] * dims[1]] = pack_normal(normal);
+
+    }}}
+
+    normals
+}
+
+// The gradient of emptiness around the voxel, closer neighbors weigh more.
+// Coords outside of the dims are empty.
+pub fn estimate_normal(bit_voxels : &BitVoxels, coords : [usize ; 3])
+    -> na::Vector3<f32>
+{
+    let dims = bit_voxels.dims();
+
+    let is_empty = |c : [i32 ; 3]|
+        c.iter().zip(dims.iter()).any(|(&c, &d)| c < 0 || c >= d as i32)
+        || !bit_voxels.get_voxel([c[0] as usize, c[1] as usize, c[2] as usize]);
+
+    let origin = [coords[0] as i32, coords[1] as i32, coords[2] as i32];
+
+    let mut gradient = na::Vector3::zeros();
+    let mut face_sum = na::Vector3::zeros();
+
+    for dx in -GRADIENT_RADIUS..=GRADIENT_RADIUS {
+    for dy in -GRADIENT_RADIUS..=GRADIENT_RADIUS {
+    for dz in -GRADIENT_RADIUS..=GRADIENT_RADIUS {
+
+        let offset = na::Vector3::new(dx as f32, dy as f32, dz as f32);
+        let distance_squared = offset.norm_squared();
+
+        if distance_squared == 0.0
+            || !is_empty([origin[0] + dx, origin[1] + dy, origin[2] + dz])
+        {
+            continue;
+        }
+
+        gradient += offset / distance_squared;
+
+        if distance_squared == 1.0
+        {
+            face_sum += offset;
+        }
+
+    }}}
+
+    // A thin or symmetric shape can cancel out the gradient,
+    // the open faces are used instead
+    if gradient.norm_squared() > 1e-4
+    {
+        gradient.normalize()
+    }
+    else if face_sum.norm_squared() > 1e-4
+    {
+        face_sum.normalize()
+    }
+    else
+    {
+        na::Vector3::y()
+    }
+}
+
+pub fn pack_normal(normal : na::Vector3<f32>)
+    -> u32
+{
+    let to_snorm = |c : f32| ((c.max(-1.0).min(1.0) * 127.0).round() as i8) as u8;
+
+    u32::from_le_bytes([to_snorm(normal.x), to_snorm(normal.y), to_snorm(normal.z), SURFACE_FLAG])
+}
+
+// None for voxels that aren't on the surface
+pub fn unpack_normal(packed : u32)
+    -> Option<na::Vector3<f32>>
+{
+    let bytes = packed.to_le_bytes();
+
+    if bytes[3] != SURFACE_FLAG
+    {
+        return None;
+    }
+
+    let from_snorm = |b : u8| (b as i8) as f32 / 127.0;
+
+    Some(na::Vector3::new(from_snorm(bytes[0]), from_snorm(bytes[1]), from_snorm(bytes[2])))
+}
@@ -1,4 +1,5 @@
 use std::sync::{Arc};
+use std::time::{Duration, Instant, SystemTime};
 
 use super::super::world_engine as world_eng;
 use world_eng::object as world_objs;
@@ -7,11 +8,36 @@ use world_eng::object as world_objs;
 
 use world_objs::VoxelPrefab;
 use world_objs::standard_voxel_prefab::StandardVoxelPrefab;
-// use nalgebra as na;
+use world_objs::icon_renderer::IconRenderer;
+use nalgebra as na;
 
 pub struct PrefabManager
 {
     pub prefabs : Vec<Arc<dyn VoxelPrefab + Send + Sync>>,
+
+    // The .vox file each prefab was loaded from, if any (same indexing as prefabs)
+    sources : Vec<Option<PrefabSource>>,
+
+    // The structures of the registered scenes, their blocks are in prefabs
+    pub structures : Vec<PrefabStructure>,
+
+    last_poll : Instant,
+}
+
+// A watched prefab file, reloaded when its modification time changes
+struct PrefabSource
+{
+    path : String,
+    modified : Option<SystemTime>,
+}
+
+const RELOAD_POLL_INTERVAL : Duration = Duration::from_millis(500);
+
+// A multi block object or room, made of registered prefabs.
+// Each block is stored with its offset from the structure's minimum corner.
+pub struct PrefabStructure
+{
+    pub blocks : Vec<(na::Vector3<i32>, u8)>,
 }
 
 impl PrefabManager
@@ -19,16 +45,146 @@ impl PrefabManager
     pub fn new()
         -> PrefabManager
     {
-        let prefabs : Vec<Arc<dyn VoxelPrefab + Send + Sync>> =
-            vec!(
-                Arc::new(StandardVoxelPrefab::new("resources/magica voxels/32 set/bricks.vox")),
-                Arc::new(StandardVoxelPrefab::new("resources/magica voxels/32 set/plank_tile.vox")),
-                Arc::new(StandardVoxelPrefab::new("resources/magica voxels/32 set/stone_stairs.vox")),
-                Arc::new(StandardVoxelPrefab::new("resources/magica voxels/32 set/grass.vox")),
-                Arc::new(StandardVoxelPrefab::new("resources/magica voxels/32 set/ridged_stone.vox")),
-            );
+        let mut prefab_manager = 
+            PrefabManager {prefabs : Vec::new(), sources : Vec::new(), structures : Vec::new(), last_poll : Instant::now()};
+
+        for path in &[
+            "resources/magica voxels/32 set/bricks.vox",
+            "resources/magica voxels/32 set/plank_tile.vox",
+            "resources/magica voxels/32 set/stone_stairs.vox",
+            "resources/magica voxels/32 set/grass.vox",
+            "resources/magica voxels/32 set/ridged_stone.vox",
+        ]
+        {
+            prefab_manager.register_file(path);
+        }
+
+        // Scenes are registered here too, the prefab images are sized after this
+        for path in &[
+            "resources/magica voxels/32 set/stool.vox",
+        ]
+        {
+            let structure = prefab_manager.register_scene(path);
+            prefab_manager.structures.push(structure);
+        }
             
-        PrefabManager {prefabs}
+        prefab_manager
+    }
+
+    // Registers a single model prefab that is reloaded whenever its file changes
+    pub fn register_file(&mut self, vox_file_path : &str)
+        -> u8
+    {
+        let block = self.register(Arc::new(StandardVoxelPrefab::new(vox_file_path)));
+
+        self.sources[block as usize] = 
+            Some(PrefabSource {path : vox_file_path.to_string(), modified : modified_time(vox_file_path)});
+
+        block
+    }
+
+    // Registers every block of a .vox scene as a prefab
+    pub fn register_scene(&mut self, vox_file_path : &str)
+        -> PrefabStructure
+    {
+        let mut blocks = Vec::new();
+
+        for (offset, prefab) in StandardVoxelPrefab::from_scene(vox_file_path)
+        {
+            blocks.push((offset, self.register(Arc::new(prefab))));
+        }
+
+        PrefabStructure {blocks}
+    }
+
+    // returns the block id of the new prefab
+    pub fn register(&mut self, prefab : Arc<dyn VoxelPrefab + Send + Sync>)
+        -> u8
+    {
+        // u8::MAX is reserved for empty blocks
+        if self.prefabs.len() >= std::u8::MAX as usize
+        {
+            panic!("Too many prefabs registered!");
+        }
+
+        self.prefabs.push(prefab);
+        self.sources.push(None);
+
+        (self.prefabs.len() - 1) as u8
+    }
+
+    // Polls the source files of prefabs and reloads the ones that were modified.
+    // A prefab that fails to reload keeps its old version.
+    // Returns the indices of reloaded prefabs.
+    pub fn reload_changed(&mut self)
+        -> Vec<usize>
+    {
+        if self.last_poll.elapsed() < RELOAD_POLL_INTERVAL
+        {
+            return Vec::new();
+        }
+
+        self.last_poll = Instant::now();
+
+        let mut reloaded = Vec::new();
+
+        for (index, source) in self.sources.iter_mut().enumerate()
+        {
+            let source = match source
+            {
+                Some(source) => source,
+                None => continue,
+            };
+
+            let modified = modified_time(&source.path);
+
+            if modified == source.modified
+            {
+                continue;
+            }
+
+            source.modified = modified;
+
+            match StandardVoxelPrefab::load(&source.path)
+            {
+                Ok(prefab) => 
+                {
+                    self.prefabs[index] = Arc::new(prefab);
+                    reloaded.push(index);
+                },
+                Err(err) => println!("Prefab reload failed, keeping the old version: {}", err),
+            }
+        }
+
+        reloaded
+    }
+
+    // Saves an icon of every prefab to <directory>/<block id>.png,
+    // file backed prefabs also get their file's name (<block id>_<name>.png)
+    pub fn render_icons(&self, directory : &str, size : u32)
+        -> image::ImageResult<()>
+    {
+        std::fs::create_dir_all(directory)?;
+
+        let renderer = IconRenderer::new(size);
+
+        for (index, prefab) in self.prefabs.iter().enumerate()
+        {
+            let file_stem =
+                self.sources[index].as_ref()
+                .and_then(|source| std::path::Path::new(&source.path).file_stem())
+                .map(|stem| format!("_{}", stem.to_string_lossy()))
+                .unwrap_or_default();
+
+            renderer.save(prefab.as_ref(), &format!("{}/{}{}.png", directory, index, file_stem))?;
+        }
+
+        Ok(())
     }
 }
 
+fn modified_time(path : &str)
+    -> Option<SystemTime>
+{
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}